@@ -0,0 +1,552 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A language server speaking LSP over stdio, for editors to format journals and show their
+//! syntax errors.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use lsp_server::{
+    Connection, ErrorCode, ExtractError, Message, Notification, Request, Response, ResponseError,
+};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics, ShowMessage,
+};
+use lsp_types::request::{Formatting, OnTypeFormatting, RangeFormatting, Request as _};
+use lsp_types::{
+    DiagnosticSeverity, DocumentFormattingParams, DocumentOnTypeFormattingOptions,
+    DocumentOnTypeFormattingParams, DocumentRangeFormattingParams, MessageType, OneOf, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, ShowMessageParams,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Uri,
+};
+
+use ledger_beautifier::{
+    detect_line_ending, format_item_at, format_range, minimal_edits, Arguments, Diagnostic,
+    Document, ParseError, Settings, Severity,
+};
+
+/// Runs the server until the client asks it to exit.
+pub fn run(options: &Arguments) -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(connection, options)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Answers the client on the other end of `connection` until it asks the server to exit.
+fn serve(connection: Connection, options: &Arguments) -> Result<()> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL,
+        )),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        // Re-aligns the transaction when a posting is finished.
+        document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+            first_trigger_character: "\n".to_string(),
+            more_trigger_character: None,
+        }),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    let mut server = Server {
+        connection: &connection,
+        options,
+        documents: HashMap::new(),
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                server.request(request)?;
+            }
+            // A notification that cannot be handled must not stop the server, the client is told
+            // and the next message is read.
+            Message::Notification(notification) => {
+                if let Err(err) = server.notification(notification) {
+                    server.show_error(&err)?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    options: &'a Arguments,
+    /// The open documents, kept parsed between edits.
+    documents: HashMap<Uri, Document>,
+}
+
+impl Server<'_> {
+    /// Answers `request`, with an error response when it cannot be handled. Only fails when the
+    /// response cannot be sent.
+    fn request(&mut self, request: Request) -> Result<()> {
+        let id = request.id.clone();
+        let response = match self.respond(request) {
+            Ok(result) => Response::new_ok(id, result),
+            Err(error) => Response::new_err(id, error.code, error.message),
+        };
+        Ok(self.connection.sender.send(response.into())?)
+    }
+
+    fn respond(&mut self, request: Request) -> Result<serde_json::Value, ResponseError> {
+        let result = match request.method.as_str() {
+            Formatting::METHOD => {
+                let params = extract::<DocumentFormattingParams>(request)?;
+                self.format(&params.text_document.uri, None)
+            }
+            RangeFormatting::METHOD => {
+                let params = extract::<DocumentRangeFormattingParams>(request)?;
+                self.format(&params.text_document.uri, Some(params.range))
+            }
+            OnTypeFormatting::METHOD => {
+                let params = extract::<DocumentOnTypeFormattingParams>(request)?;
+                let position = params.text_document_position;
+                self.format_item(&position.text_document.uri, position.position)
+            }
+            _ => {
+                let message = format!("unsupported request {}", request.method);
+                return Err(response_error(ErrorCode::MethodNotFound, message));
+            }
+        };
+        match result {
+            Ok(edits) => serde_json::to_value(edits)
+                .map_err(|err| response_error(ErrorCode::InternalError, err)),
+            // The document cannot be formatted, its diagnostics already tell why.
+            Err(err) if err.is::<ParseError>() => Ok(serde_json::Value::Null),
+            Err(err) => Err(response_error(ErrorCode::InternalError, err)),
+        }
+    }
+
+    /// Shows `err` to the user, for failures that have no request to answer.
+    fn show_error(&self, err: &anyhow::Error) -> Result<()> {
+        let params = ShowMessageParams {
+            typ: MessageType::ERROR,
+            message: format!("ledger-beautifier: {err}"),
+        };
+        let notification = Notification::new(ShowMessage::METHOD.to_string(), params);
+        Ok(self.connection.sender.send(notification.into())?)
+    }
+
+    fn notification(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                let document = params.text_document;
+                // The document is kept even if its configuration is broken, so that it can be
+                // edited and formatted once it is fixed.
+                let settings = self.settings(&document.uri).or_else(|err| {
+                    self.show_error(&err)?;
+                    anyhow::Ok(Settings::default())
+                })?;
+                self.documents.insert(
+                    document.uri.clone(),
+                    Document::new(&document.text, settings)?,
+                );
+                self.publish_diagnostics(&document.uri)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                let Some(document) = self.documents.get_mut(&uri) else {
+                    return Ok(());
+                };
+                for change in params.content_changes {
+                    match change.range {
+                        Some(range) => {
+                            let text = document.text();
+                            document.edit(&ledger_beautifier::TextEdit {
+                                range: offset(text, range.start)..offset(text, range.end),
+                                new_text: change.text,
+                            })?
+                        }
+                        None => document.set_text(change.text)?,
+                    }
+                }
+                self.publish_diagnostics(&uri)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                self.send_diagnostics(params.text_document.uri, vec![])
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Formats the document at `uri`, or the items that overlap `range`.
+    fn format(&mut self, uri: &Uri, range: Option<Range>) -> Result<Vec<TextEdit>> {
+        let settings = self.settings(uri)?;
+        let document = self
+            .documents
+            .get_mut(uri)
+            .ok_or_else(|| anyhow!("{} is not open", uri.as_str()))?;
+        if *document.settings() != settings {
+            document.set_settings(settings.clone());
+        }
+        let Some(range) = range else {
+            let formatted = document.format()?;
+            let code = document.text();
+            let edits = minimal_edits(code, &formatted);
+            return Ok(edits.iter().map(|edit| lsp_edit(code, edit)).collect());
+        };
+        let code = document.text();
+        let range = offset(code, range.start)..offset(code, range.end);
+        let edits = format_range(&with_final_newline(code), range, &settings)?;
+        Ok(edits.iter().map(|edit| lsp_edit(code, edit)).collect())
+    }
+
+    /// Formats the transaction or directive at `position`.
+    fn format_item(&self, uri: &Uri, position: Position) -> Result<Vec<TextEdit>> {
+        let settings = self.settings(uri)?;
+        let code = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| anyhow!("{} is not open", uri.as_str()))?
+            .text();
+        let source = with_final_newline(code);
+        let Some(item) = format_item_at(&source, offset(code, position), &settings)? else {
+            return Ok(vec![]);
+        };
+        let start = item.range.start;
+        Ok(minimal_edits(&source[item.range], &item.new_text)
+            .into_iter()
+            .map(|edit| {
+                let edit = ledger_beautifier::TextEdit {
+                    range: start + edit.range.start..start + edit.range.end,
+                    new_text: edit.new_text,
+                };
+                lsp_edit(code, &edit)
+            })
+            .collect())
+    }
+
+    /// Resolves the settings of the document at `uri` like the command line does for files.
+    fn settings(&self, uri: &Uri) -> Result<Settings> {
+        Settings::resolve(self.options, file_path(uri).as_deref())
+    }
+
+    fn publish_diagnostics(&mut self, uri: &Uri) -> Result<()> {
+        let Some(document) = self.documents.get_mut(uri) else {
+            return Ok(());
+        };
+        // Formatting also finds the errors the parser does not, and fills the document's cache for
+        // the next formatting request.
+        let diagnostics = match document.format() {
+            Ok(_) => vec![],
+            Err(err) => match err.downcast_ref::<ParseError>() {
+                Some(parse_error) => parse_error
+                    .diagnostics
+                    .iter()
+                    .map(|diagnostic| lsp_diagnostic(document.text(), diagnostic))
+                    .collect(),
+                None => vec![lsp_types::Diagnostic {
+                    range: Range::default(),
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some("ledger-beautifier".to_string()),
+                    message: err.to_string(),
+                    ..Default::default()
+                }],
+            },
+        };
+        self.send_diagnostics(uri.clone(), diagnostics)
+    }
+
+    fn send_diagnostics(&self, uri: Uri, diagnostics: Vec<lsp_types::Diagnostic>) -> Result<()> {
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        Ok(self.connection.sender.send(notification.into())?)
+    }
+}
+
+fn extract<P: serde::de::DeserializeOwned>(request: Request) -> Result<P, ResponseError> {
+    let method = request.method.clone();
+    match request.extract(&method) {
+        Ok((_, params)) => Ok(params),
+        Err(ExtractError::MethodMismatch(request)) => Err(response_error(
+            ErrorCode::InternalError,
+            format!("unexpected {}", request.method),
+        )),
+        Err(ExtractError::JsonError { method, error }) => Err(response_error(
+            ErrorCode::InvalidParams,
+            format!("invalid {method}: {error}"),
+        )),
+    }
+}
+
+fn response_error(code: ErrorCode, message: impl ToString) -> ResponseError {
+    ResponseError {
+        code: code as i32,
+        message: message.to_string(),
+        data: None,
+    }
+}
+
+/// Converts `edit` of the text `code` with a line break appended for the parser.
+fn lsp_edit(code: &str, edit: &ledger_beautifier::TextEdit) -> TextEdit {
+    let mut new_text = edit.new_text.clone();
+    let mut end = edit.range.end;
+    // The line break added for the parser is not part of the document.
+    if end > code.len() {
+        end = code.len();
+        new_text.truncate(new_text.trim_end_matches(['\r', '\n']).len());
+    }
+    let start = edit.range.start.min(end);
+    TextEdit {
+        range: Range::new(position(code, start), position(code, end)),
+        new_text,
+    }
+}
+
+/// The parser expects the last line to end with a line break.
+fn with_final_newline(code: &str) -> String {
+    code.to_string() + detect_line_ending(code)
+}
+
+fn lsp_diagnostic(code: &str, diagnostic: &Diagnostic) -> lsp_types::Diagnostic {
+    let point = |line: usize, column: usize| {
+        let start = line_start(code, line);
+        position(code, (start + column).min(code.len()))
+    };
+    let mut message = diagnostic.message.clone();
+    if let Some(hint) = &diagnostic.hint {
+        message += &format!(" ({hint})");
+    }
+    lsp_types::Diagnostic {
+        range: Range::new(
+            point(diagnostic.start.line, diagnostic.start.column),
+            point(diagnostic.end.line, diagnostic.end.column),
+        ),
+        severity: Some(match diagnostic.severity {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+        }),
+        source: Some("ledger-beautifier".to_string()),
+        message,
+        ..Default::default()
+    }
+}
+
+/// The byte offset where line `line`, counting from 0, starts.
+fn line_start(code: &str, line: usize) -> usize {
+    if line == 0 {
+        return 0;
+    }
+    code.match_indices('\n')
+        .nth(line - 1)
+        .map_or(code.len(), |(index, _)| index + 1)
+}
+
+/// The LSP position of the byte offset `offset`, whose character counts UTF-16 code units.
+fn position(code: &str, offset: usize) -> Position {
+    let before = &code[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    let character = before[line_start..].encode_utf16().count();
+    Position::new(line as u32, character as u32)
+}
+
+/// The byte offset of the LSP position `position`, clamped to the document.
+fn offset(code: &str, position: Position) -> usize {
+    let start = line_start(code, position.line as usize);
+    let line = &code[start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let mut units = 0;
+    for (index, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return start + index;
+        }
+        units += c.len_utf16();
+    }
+    start + line.len()
+}
+
+/// The path of a `file:` URI.
+fn file_path(uri: &Uri) -> Option<PathBuf> {
+    if uri.scheme().map(|scheme| scheme.as_str()) != Some("file") {
+        return None;
+    }
+    let path = uri.path().as_estr().decode().into_string().ok()?;
+    Some(PathBuf::from(path.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ledger_beautifier::{beautify, Parser};
+    use lsp_server::RequestId;
+    use lsp_types::notification::{Exit, Initialized};
+    use lsp_types::request::{Initialize, Shutdown};
+    use lsp_types::{
+        DidOpenTextDocumentParams, FormattingOptions, InitializeParams, InitializedParams,
+        TextDocumentIdentifier, TextDocumentItem,
+    };
+
+    const JOURNAL: &str = "2024/01/02 Shop
+    Expenses:Food  10 EUR
+    Assets:Cash
+
+2024/01/01 Salary
+  Assets:Bank   100 EUR
+  Income:Salary
+";
+
+    struct Client {
+        connection: Connection,
+        next_id: i32,
+    }
+
+    impl Client {
+        /// Starts a server on another thread and initializes it.
+        fn start() -> (Self, std::thread::JoinHandle<Result<()>>) {
+            let (server, connection) = Connection::memory();
+            let thread = std::thread::spawn(move || {
+                serve(server, &Arguments::parse_from(["ledger-beautifier", "lsp"]))
+            });
+            let mut client = Client {
+                connection,
+                next_id: 0,
+            };
+            let response = client.request::<Initialize>(InitializeParams::default());
+            assert!(response.error.is_none());
+            client.notify::<Initialized>(InitializedParams {});
+            (client, thread)
+        }
+
+        fn request<R: lsp_types::request::Request>(&mut self, params: R::Params) -> Response {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), R::METHOD.to_string(), params);
+            self.connection.sender.send(request.into()).unwrap();
+            self.response(id)
+        }
+
+        /// Waits for the response to `id`, skipping the notifications sent before it.
+        fn response(&self, id: RequestId) -> Response {
+            loop {
+                if let Message::Response(response) = self.connection.receiver.recv().unwrap() {
+                    if response.id == id {
+                        return response;
+                    }
+                }
+            }
+        }
+
+        fn notify<N: lsp_types::notification::Notification>(&self, params: N::Params) {
+            let notification = Notification::new(N::METHOD.to_string(), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        fn stop(mut self, thread: std::thread::JoinHandle<Result<()>>) {
+            let response = self.request::<Shutdown>(());
+            assert!(response.error.is_none());
+            self.notify::<Exit>(());
+            thread.join().unwrap().unwrap();
+        }
+    }
+
+    fn uri() -> Uri {
+        "untitled:journal.ledger".parse().unwrap()
+    }
+
+    fn apply(code: &str, edits: &[TextEdit]) -> String {
+        let mut code = code.to_string();
+        for edit in edits.iter().rev() {
+            let range = offset(&code, edit.range.start)..offset(&code, edit.range.end);
+            code.replace_range(range, &edit.new_text);
+        }
+        code
+    }
+
+    fn edits(response: Response) -> Vec<TextEdit> {
+        assert!(response.error.is_none(), "{:?}", response.error);
+        serde_json::from_value(response.result.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn formats_documents_and_ranges() {
+        let (mut client, thread) = Client::start();
+        client.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri(), "ledger".into(), 1, JOURNAL.into()),
+        });
+
+        let response = client.request::<Formatting>(DocumentFormattingParams {
+            text_document: TextDocumentIdentifier::new(uri()),
+            options: FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        });
+        let options = Arguments::parse_from(["ledger-beautifier", "lsp"]);
+        let settings = Settings::resolve(&options, None).unwrap();
+        let expected = beautify(&(JOURNAL.to_string() + "\n"), &settings).unwrap();
+        assert_eq!(apply(JOURNAL, &edits(response)), expected);
+
+        // Only the transaction at the start of the range is formatted, and it is not sorted.
+        let response = client.request::<RangeFormatting>(DocumentRangeFormattingParams {
+            text_document: TextDocumentIdentifier::new(uri()),
+            range: Range::new(Position::new(1, 0), Position::new(1, 0)),
+            options: FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        });
+        let formatted = apply(JOURNAL, &edits(response));
+        assert_ne!(formatted, JOURNAL);
+        assert!(formatted.starts_with("2024/01/02 Shop\n"));
+        assert!(
+            formatted.ends_with("\n2024/01/01 Salary\n  Assets:Bank   100 EUR\n  Income:Salary\n")
+        );
+
+        client.stop(thread);
+    }
+
+    #[test]
+    fn keeps_running_after_invalid_messages() {
+        let (mut client, thread) = Client::start();
+        let notification = Notification::new(
+            DidOpenTextDocument::METHOD.to_string(),
+            serde_json::json!({ "text_document": 1 }),
+        );
+        client.connection.sender.send(notification.into()).unwrap();
+        match client.connection.receiver.recv().unwrap() {
+            Message::Notification(notification) => {
+                assert_eq!(notification.method, ShowMessage::METHOD)
+            }
+            message => panic!("unexpected {message:?}"),
+        }
+
+        let id = RequestId::from(100);
+        let request = Request::new(
+            id.clone(),
+            Formatting::METHOD.to_string(),
+            serde_json::json!({ "textDocument": 1 }),
+        );
+        client.connection.sender.send(request.into()).unwrap();
+        let error = client.response(id).error.unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidParams as i32);
+
+        // The document was never opened.
+        let response = client.request::<Formatting>(DocumentFormattingParams {
+            text_document: TextDocumentIdentifier::new(uri()),
+            options: FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        });
+        assert_eq!(
+            response.error.unwrap().code,
+            ErrorCode::InternalError as i32
+        );
+
+        client.stop(thread);
+    }
+}
@@ -4,48 +4,361 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use anyhow::Result;
-use colored::*;
+mod lsp;
+mod report;
+mod serve;
 
-use ledger_beautifier::beautify;
-use ledger_beautifier::{Arguments, Parser};
+use std::io::IsTerminal;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use ledger_beautifier::{apply_edits, detect_line_ending, format_item_at};
+use ledger_beautifier::{beautify, beautify_journal, beautify_with_cursor, convert_to_beancount};
+use ledger_beautifier::{categorize, minimal_edits, Classifier, ConvertFormat, ImportRules};
+use ledger_beautifier::{import_csv, import_ofx, is_ofx, parse_journal, remove_duplicates};
+use ledger_beautifier::{Arguments, Command, CommandFactory, ExportFormat, Parser, Settings};
+use ledger_beautifier::{Journal, MessageFormat};
+use report::{FileReport, Status};
 
 fn main() {
     let mut options = Arguments::parse();
-    if options.files.is_empty() {
+    // Diagnostics go to stderr and results to stdout, both are colored only on terminals.
+    colored::control::set_override(
+        std::io::stdout().is_terminal() && std::io::stderr().is_terminal(),
+    );
+    if options.output.is_some() && options.files.len() > 1 {
+        Arguments::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--output accepts at most one input file, use --output-dir instead",
+            )
+            .exit();
+    }
+    if options.cursor_offset.is_some() && options.files.len() > 1 {
+        Arguments::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--cursor-offset accepts at most one input file",
+            )
+            .exit();
+    }
+    if options.format_at.is_some() && options.files.len() > 1 {
+        Arguments::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--format-at accepts at most one input file",
+            )
+            .exit();
+    }
+    let files = if options.files.is_empty() {
         options.inplace = false;
-        beautify_file(None, &mut options).unwrap();
+        vec![None]
     } else {
-        options.inplace |= options.files.len() > 1;
-        let files = options.files.clone();
-        for file in files {
-            let r = beautify_file(Some(file), &mut options);
-            if let (false, Err(_)) = (options.inplace, &r) {
-                r.unwrap()
-            } else if let Err(err) = r {
-                println!("{} ({})", "could not format".red(), err.to_string().red());
-            }
+        options.inplace |= options.files.len() > 1 && options.output_dir.is_none();
+        options.files.iter().cloned().map(Some).collect()
+    };
+    if options.command.is_some() || options.from_json {
+        // Subcommands produce something other than ledger files, never write it over the input.
+        options.inplace = false;
+    }
+    if matches!(options.command, Some(Command::Lsp)) {
+        if let Err(err) = lsp::run(&options) {
+            eprintln!("ledger-beautifier lsp: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if matches!(options.command, Some(Command::Serve)) {
+        if let Err(err) = serve::run(&options) {
+            eprintln!("ledger-beautifier serve: {err}");
+            std::process::exit(1);
         }
+        return;
+    }
+    if options.print_config {
+        print_config(&files, &options);
+        return;
+    }
+    let mut failed = false;
+    for file in files {
+        let path = file.clone().unwrap_or_else(|| stdin_label(&options));
+        let report = match &options.command {
+            None if options.from_json => import_json_file(file, &options),
+            None => beautify_file(file, &options),
+            Some(Command::Export { format, delimiter }) => {
+                export_file(file, *format, *delimiter, &options)
+            }
+            Some(Command::Convert { to }) => convert_file(file, *to, &options),
+            Some(Command::Import { rules, journal }) => {
+                import_file(file, rules, journal.as_deref(), &options)
+            }
+            Some(Command::Lsp | Command::Serve) => unreachable!("servers do not read files"),
+        };
+        let report = match report {
+            Ok(report) => report,
+            Err(err) => FileReport::error(path, err),
+        };
+        failed |= report.status == Status::Error;
+        report::print_report(&report, options.message_format);
+    }
+    if failed {
+        std::process::exit(1);
     }
 }
 
-fn beautify_file(file: Option<String>, options: &mut Arguments) -> Result<()> {
+/// Reads `file`, or stdin, and resolves the settings that apply to it. Returns the contents, the
+/// settings and the name the input is reported as.
+fn read_input(file: Option<String>, options: &Arguments) -> Result<(String, Settings, String)> {
     let code = if let Some(file) = &file {
         let mut file = std::fs::File::open(file)?;
-        read_to_string(&mut file, None)?.0 + "\n"
+        read_to_string(&mut file, None)?.0
+    } else {
+        read_to_string(&mut std::io::stdin(), None)?.0
+    };
+    let path = file.clone().or_else(|| options.stdin_filepath.clone());
+    let settings = Settings::resolve(options, path.as_deref().map(Path::new))?;
+    let path = file.unwrap_or_else(|| stdin_label(options));
+    Ok((code, settings, path))
+}
+
+fn beautify_file(file: Option<String>, options: &Arguments) -> Result<FileReport> {
+    let (code, settings, path) = read_input(file, options)?;
+    if settings.excluded {
+        let formatted =
+            (!options.inplace && options.output.is_none() && options.output_dir.is_none())
+                .then_some(code);
+        let mut report = FileReport::new(path, Status::Unchanged, formatted);
+        report.cursor_offset = options.cursor_offset;
+        return Ok(report);
+    }
+    let newline = detect_line_ending(&code);
+    let original = code;
+    let code = original.clone() + newline;
+    let (result, cursor_offset) = match (options.cursor_offset, options.format_at) {
+        (Some(cursor), _) => {
+            let (result, cursor) = beautify_with_cursor(code.as_str(), &settings, cursor)?;
+            (result, Some(cursor))
+        }
+        (None, Some(offset)) => {
+            let edits = Vec::from_iter(format_item_at(code.as_str(), offset, &settings)?);
+            let mut result = apply_edits(&code, &edits);
+            // Drop the line break added for the parser, the item keeps the ones it ends with.
+            result.truncate(result.len() - newline.len());
+            (result, None)
+        }
+        (None, None) => (beautify(code.as_str(), &settings)?, None),
+    };
+    let result_extra_newline = result.clone() + newline;
+    let status = if result_extra_newline == code {
+        Status::Unchanged
+    } else {
+        Status::Formatted
+    };
+    let edits = if status == Status::Formatted && options.message_format == MessageFormat::Json {
+        minimal_edits(&original, &result)
+    } else {
+        vec![]
+    };
+    let mut report = if let Some(output) = output_path(&path, options, None)? {
+        write_output(path, status, &output, &result)?
+    } else if !options.inplace {
+        FileReport::new(path, status, Some(result))
+    } else {
+        if status == Status::Formatted {
+            std::fs::write(&path, result.as_bytes())
+                .map_err(|err| anyhow!("file formatted but could not write back: {err}"))?;
+        }
+        FileReport::new(path, status, None)
+    };
+    report.edits = edits;
+    report.cursor_offset = cursor_offset;
+    Ok(report)
+}
+
+fn import_json_file(file: Option<String>, options: &Arguments) -> Result<FileReport> {
+    let (code, settings, path) = read_input(file, options)?;
+    let journal: Journal = serde_json::from_str(&code)?;
+    let result = beautify_journal(&journal, &settings)?;
+    match output_path(&path, options, Some("ledger"))? {
+        Some(output) => write_output(path, Status::Formatted, &output, &result),
+        None => Ok(FileReport::new(path, Status::Formatted, Some(result))),
+    }
+}
+
+fn export_file(
+    file: Option<String>,
+    format: ExportFormat,
+    delimiter: Option<char>,
+    options: &Arguments,
+) -> Result<FileReport> {
+    let (code, settings, path) = read_input(file, options)?;
+    let journal = parse_journal(&code, &settings)?;
+    let delimiter = |default| {
+        u8::try_from(delimiter.unwrap_or(default))
+            .map_err(|_| anyhow!("--delimiter must be a single ASCII character"))
+    };
+    let (result, extension) = match format {
+        ExportFormat::Json => (serde_json::to_string_pretty(&journal)? + "\n", "json"),
+        ExportFormat::Csv => (journal.to_csv(delimiter(',')?)?, "csv"),
+        ExportFormat::Tsv => (journal.to_csv(delimiter('\t')?)?, "tsv"),
+    };
+    match output_path(&path, options, Some(extension))? {
+        Some(output) => write_output(path, Status::Exported, &output, &result),
+        None => Ok(FileReport::new(path, Status::Exported, Some(result))),
+    }
+}
+
+fn convert_file(
+    file: Option<String>,
+    to: ConvertFormat,
+    options: &Arguments,
+) -> Result<FileReport> {
+    let (code, settings, path) = read_input(file, options)?;
+    let (conversion, extension) = match to {
+        ConvertFormat::Beancount => (convert_to_beancount(&code, &settings)?, "beancount"),
+    };
+    let mut report = match output_path(&path, options, Some(extension))? {
+        Some(output) => write_output(path, Status::Converted, &output, &conversion.text)?,
+        None => FileReport::new(path, Status::Converted, Some(conversion.text)),
+    };
+    report.diagnostics = conversion.diagnostics;
+    Ok(report)
+}
+
+fn import_file(
+    file: Option<String>,
+    rules: &str,
+    journal: Option<&str>,
+    options: &Arguments,
+) -> Result<FileReport> {
+    let (code, settings, path) = read_input(file, options)?;
+    let rules = ImportRules::load(Path::new(rules))?;
+    let mut imported = if is_ofx(&code) {
+        import_ofx(&code, &rules)?
     } else {
-        read_to_string(&mut std::io::stdin(), None)?.0 + "\n"
+        import_csv(&code, &rules)?
     };
-    let result = beautify(code.as_str(), options)?;
-    let result_extra_newline = result.clone() + "\n";
-    if options.inplace && result_extra_newline != code {
-        print!("{}", "file formatted ".green());
-        match std::fs::write(file.unwrap().as_str(), result.as_bytes()) {
-            Ok(_) => println!("{}", "and overwritten.".green()),
-            Err(_) => println!("{}", "but could not write back.".red()),
+    let mut skipped = vec![];
+    if let Some(journal) = journal {
+        let existing = read_journal(journal, options)
+            .map_err(|err| anyhow!("could not read the journal {journal}: {err}"))?;
+        (imported, skipped) = remove_duplicates(imported, &existing, &rules);
+        if rules.classify {
+            let classifier = Classifier::train(&existing, &rules);
+            categorize(&mut imported, &classifier, &rules);
         }
+    } else if rules.classify {
+        return Err(anyhow!(
+            "classify needs the journal to learn from, set --journal"
+        ));
     }
-    Ok(())
+    let result = beautify_journal(&imported, &settings)?;
+    let mut report = match output_path(&path, options, Some("ledger"))? {
+        Some(output) => write_output(path, Status::Imported, &output, &result)?,
+        None => FileReport::new(path, Status::Imported, Some(result)),
+    };
+    report.skipped = skipped;
+    Ok(report)
+}
+
+/// Reads and parses the journal at `path`, with the settings that apply to it.
+fn read_journal(path: &str, options: &Arguments) -> Result<Journal> {
+    let code = read_to_string(&mut std::fs::File::open(path)?, None)?.0;
+    let settings = Settings::resolve(options, Some(Path::new(path)))?;
+    parse_journal(&code, &settings)
+}
+
+fn write_output(path: String, status: Status, output: &Path, result: &str) -> Result<FileReport> {
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(output, result.as_bytes())
+        .map_err(|err| anyhow!("could not write {}: {err}", output.display()))?;
+    let mut report = FileReport::new(path, status, None);
+    report.output = Some(output.to_string_lossy().into_owned());
+    Ok(report)
+}
+
+/// Where the result for `path` should be written, if not to stdout or back over it. With
+/// `--output-dir`, the file name gets `extension` when one is given.
+fn output_path(
+    path: &str,
+    options: &Arguments,
+    extension: Option<&str>,
+) -> Result<Option<PathBuf>> {
+    if let Some(output) = &options.output {
+        return Ok(Some(PathBuf::from(output)));
+    }
+    let Some(dir) = &options.output_dir else {
+        return Ok(None);
+    };
+    if path == "<stdin>" {
+        return Err(anyhow!(
+            "--output-dir needs --stdin-filepath when reading from stdin"
+        ));
+    }
+    let mut output = Path::new(dir).join(relative_path(Path::new(path))?);
+    if let Some(extension) = extension {
+        output.set_extension(extension);
+    }
+    Ok(Some(output))
+}
+
+/// The path of `path` relative to the working directory, so it can be recreated inside the output
+/// directory. Paths outside the working directory are rejected, as they could not be mirrored
+/// without clashing with the paths inside it.
+fn relative_path(path: &Path) -> Result<PathBuf> {
+    let relative = if path.is_absolute() {
+        let cwd = std::env::current_dir()?;
+        path.strip_prefix(&cwd).unwrap_or(path)
+    } else {
+        path
+    };
+    let mut output = PathBuf::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) => output.push(name),
+            Component::CurDir => {}
+            Component::ParentDir if output.pop() => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(anyhow!(
+                    "{} is outside the working directory, so it has no place in --output-dir",
+                    path.display()
+                ));
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Prints the settings that apply to each of `files`.
+fn print_config(files: &[Option<String>], options: &Arguments) {
+    for file in files {
+        let path = file.clone().or_else(|| options.stdin_filepath.clone());
+        let settings = Settings::resolve(options, path.as_deref().map(Path::new))
+            .and_then(|settings| settings.to_toml());
+        match settings {
+            Ok(settings) => {
+                if files.len() > 1 {
+                    println!("# {}", path.unwrap_or_default());
+                }
+                print!("{settings}");
+            }
+            Err(err) => {
+                let path = file.clone().unwrap_or_else(|| stdin_label(options));
+                report::print_error(&path, &err);
+            }
+        }
+    }
+}
+
+/// The name under which stdin is reported.
+fn stdin_label(options: &Arguments) -> String {
+    options
+        .stdin_filepath
+        .clone()
+        .unwrap_or_else(|| "<stdin>".to_string())
 }
 
 /// Taken from helix-editor
@@ -131,3 +444,36 @@ pub fn read_to_string<R: std::io::Read + ?Sized>(
     }
     Ok((buf_string, encoding, has_bom))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirrors_paths_inside_the_working_directory() {
+        let cwd = std::env::current_dir().unwrap();
+        for (path, expected) in [
+            ("a.ledger", "a.ledger"),
+            ("./2024/a.ledger", "2024/a.ledger"),
+            ("2024/../2025/./a.ledger", "2025/a.ledger"),
+        ] {
+            assert_eq!(relative_path(Path::new(path)).unwrap(), Path::new(expected));
+            let absolute = cwd.join(path);
+            assert_eq!(relative_path(&absolute).unwrap(), Path::new(expected));
+        }
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_working_directory() {
+        let outside = std::env::current_dir()
+            .unwrap()
+            .parent()
+            .map(|parent| parent.join("outside.ledger"));
+        for path in ["../a.ledger", "2024/../../a.ledger"] {
+            assert!(relative_path(Path::new(path)).is_err(), "{path}");
+        }
+        if let Some(outside) = outside {
+            assert!(relative_path(&outside).is_err());
+        }
+    }
+}
@@ -0,0 +1,288 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::max;
+
+use colored::*;
+use serde::Serialize;
+
+use ledger_beautifier::{Diagnostic, MessageFormat, ParseError, Severity, Skipped, TextEdit};
+
+/// What happened to a single input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Unchanged,
+    Formatted,
+    Exported,
+    Converted,
+    Imported,
+    Error,
+}
+
+/// The outcome of formatting a single input, as reported to the user.
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub path: String,
+    pub status: Status,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Errors that are not tied to a position in the source, like I/O errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The formatted text, when it is not written to a file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted: Option<String>,
+    /// The file the formatted text was written to, when it is not the input itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// The smallest changes that format the input, with byte ranges of the input. Only computed for
+    /// `--message-format json`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub edits: Vec<TextEdit>,
+    /// Where `--cursor-offset` ends up in the formatted text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor_offset: Option<usize>,
+    /// Imported transactions left out because the journal already has them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<Skipped>,
+    #[serde(skip)]
+    cause: Option<anyhow::Error>,
+}
+
+impl FileReport {
+    pub fn new(path: String, status: Status, formatted: Option<String>) -> Self {
+        FileReport {
+            path,
+            status,
+            diagnostics: vec![],
+            error: None,
+            formatted,
+            output: None,
+            edits: vec![],
+            cursor_offset: None,
+            skipped: vec![],
+            cause: None,
+        }
+    }
+
+    pub fn error(path: String, err: anyhow::Error) -> Self {
+        let (diagnostics, error) = match err.downcast_ref::<ParseError>() {
+            Some(parse_error) => (parse_error.diagnostics.clone(), None),
+            None => (vec![], Some(err.to_string())),
+        };
+        FileReport {
+            path,
+            status: Status::Error,
+            diagnostics,
+            error,
+            formatted: None,
+            output: None,
+            edits: vec![],
+            cursor_offset: None,
+            skipped: vec![],
+            cause: Some(err),
+        }
+    }
+}
+
+/// Prints `report` in the requested format.
+pub fn print_report(report: &FileReport, format: MessageFormat) {
+    match format {
+        MessageFormat::Json => match serde_json::to_string(report) {
+            Ok(json) => println!("{json}"),
+            Err(err) => eprintln!("could not serialize report: {err}"),
+        },
+        MessageFormat::Human => {
+            if let Some(err) = &report.cause {
+                print_error(&report.path, err);
+                return;
+            }
+            for diagnostic in &report.diagnostics {
+                print_diagnostic(&report.path, diagnostic);
+            }
+            for skipped in &report.skipped {
+                print_skipped(&report.path, skipped);
+            }
+            if let Some(cursor_offset) = report.cursor_offset {
+                eprintln!("{cursor_offset}");
+            }
+            if let Some(formatted) = &report.formatted {
+                print!("{formatted}");
+            } else if let Some(output) = &report.output {
+                println!(
+                    "{}: {} {}",
+                    report.path.bold(),
+                    match report.status {
+                        Status::Exported => "file exported into".green(),
+                        Status::Converted => "file converted into".green(),
+                        Status::Imported => "file imported into".green(),
+                        _ => "file formatted into".green(),
+                    },
+                    output.green()
+                );
+            } else if report.status == Status::Formatted {
+                println!(
+                    "{}: {}",
+                    report.path.bold(),
+                    "file formatted and overwritten.".green()
+                );
+            }
+        }
+    }
+}
+
+/// Prints an imported transaction that was left out, to stderr so it does not mix with the output.
+pub fn print_skipped(path: &str, skipped: &Skipped) {
+    let transaction = &skipped.transaction;
+    let line = skipped
+        .line
+        .map(|line| format!(" (journal line {line})"))
+        .unwrap_or_default();
+    eprintln!(
+        "{}: {} {} {}: {}{}",
+        path.bold(),
+        "skipped".yellow().bold(),
+        transaction.date.as_deref().unwrap_or_default(),
+        transaction.payee.as_deref().unwrap_or_default(),
+        skipped.reason,
+        line
+    );
+}
+
+/// Prints an error that happened while formatting `path` to stderr. Parse errors are
+/// printed as source snippets, anything else as a single line.
+pub fn print_error(path: &str, err: &anyhow::Error) {
+    if let Some(parse_error) = err.downcast_ref::<ParseError>() {
+        for diagnostic in &parse_error.diagnostics {
+            print_diagnostic(path, diagnostic);
+        }
+    } else {
+        eprintln!(
+            "{}: {} ({})",
+            path.bold(),
+            "could not format".red(),
+            err.to_string().red()
+        );
+    }
+}
+
+/// Prints a diagnostic in the style of compiler errors to stderr, so it does not mix with the
+/// output:
+///
+/// ```text
+/// error: unexpected `==`
+///   --> journal.ledger:3:40
+///    |
+///  3 |     Assets:Cash                          == $ 10
+///    |                                          ^^
+///    = hint: expected `amount`
+/// ```
+pub fn print_diagnostic(path: &str, diagnostic: &Diagnostic) {
+    eprint!("{}", render_diagnostic(path, diagnostic));
+}
+
+fn render_diagnostic(path: &str, diagnostic: &Diagnostic) -> String {
+    let line_number = (diagnostic.start.line + 1).to_string();
+    let gutter = " ".repeat(line_number.len());
+    let line = diagnostic.source_line.as_str();
+    let start = char_boundary(line, diagnostic.start.column);
+    let end = if diagnostic.end.line == diagnostic.start.line {
+        char_boundary(line, diagnostic.end.column)
+    } else {
+        line.len()
+    };
+    let padding: String = line[..start]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = max(line[start..end].chars().count(), 1);
+
+    let (label, marker) = match diagnostic.severity {
+        Severity::Error => ("error".red().bold(), "^".repeat(width).red().bold()),
+        Severity::Warning => ("warning".yellow().bold(), "^".repeat(width).yellow().bold()),
+    };
+    let mut text = format!("{}: {}\n", label, diagnostic.message.bold());
+    text += &format!(
+        "{}{} {}:{}:{}\n",
+        gutter,
+        "-->".blue().bold(),
+        path,
+        diagnostic.start.line + 1,
+        line[..start].chars().count() + 1
+    );
+    text += &format!("{} {}\n", gutter, "|".blue().bold());
+    text += &format!(
+        "{} {} {}\n",
+        line_number.blue().bold(),
+        "|".blue().bold(),
+        line
+    );
+    text += &format!("{} {} {}{}\n", gutter, "|".blue().bold(), padding, marker);
+    if let Some(hint) = &diagnostic.hint {
+        text += &format!("{} {} {}\n", gutter, "= hint:".blue().bold(), hint);
+    }
+    text
+}
+
+fn char_boundary(line: &str, column: usize) -> usize {
+    let mut column = column.min(line.len());
+    while !line.is_char_boundary(column) {
+        column -= 1;
+    }
+    column
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ledger_beautifier::Position;
+
+    fn diagnostic(line: usize, start: usize, end: usize, source_line: &str) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: "unexpected `==`".to_string(),
+            hint: Some("expected `amount`".to_string()),
+            start: Position {
+                line,
+                column: start,
+            },
+            end: Position { line, column: end },
+            source_line: source_line.to_string(),
+        }
+    }
+
+    #[test]
+    fn renders_source_snippets() {
+        colored::control::set_override(false);
+        let line = "    Assets:Cash   == $ 10";
+        assert_eq!(
+            render_diagnostic("journal.ledger", &diagnostic(9, 18, 20, line)),
+            "error: unexpected `==`
+  --> journal.ledger:10:19
+   |
+10 |     Assets:Cash   == $ 10
+   |                   ^^
+   = hint: expected `amount`
+"
+        );
+
+        // Columns count characters, tabs are kept so the marker lines up, and empty ranges are
+        // marked with one caret.
+        let line = "\tcafé ]";
+        let mut warning = diagnostic(0, 7, 7, line);
+        warning.severity = Severity::Warning;
+        warning.hint = None;
+        assert_eq!(
+            render_diagnostic("-", &warning),
+            "warning: unexpected `==`
+ --> -:1:7
+  |
+1 | \tcafé ]
+  | \t     ^
+"
+        );
+    }
+}
@@ -0,0 +1,395 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A daemon answering JSON-RPC 2.0 requests, one per line on stdin, with one response per line on
+//! stdout. It keeps the inputs of each path parsed between requests, so editors can format many
+//! buffers without starting a process for each.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use ledger_beautifier::{minimal_edits, Arguments, Diagnostic, Document, Settings};
+
+use crate::read_to_string;
+use crate::report::{FileReport, Status};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Runs the daemon until stdin is closed.
+pub fn run(options: &Arguments) -> Result<()> {
+    let mut server = Server {
+        options,
+        documents: HashMap::new(),
+    };
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = server.handle(&line) {
+            let mut stdout = std::io::stdout().lock();
+            writeln!(stdout, "{}", serde_json::to_string(&response)?)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Request {
+    /// Absent for notifications, which are not answered.
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// The input of a request. Without `text`, the file at `path` is read. `path` also decides the
+/// configuration that applies, like `--stdin-filepath`, and is what the server remembers the input
+/// by: inputs without it are parsed from scratch each time.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Params {
+    path: Option<String>,
+    text: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+/// The answer to `diagnostics`.
+#[derive(Serialize)]
+struct DiagnosticsReport {
+    path: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+struct Server<'a> {
+    options: &'a Arguments,
+    /// The last text of each path, kept parsed so the next one is reparsed incrementally.
+    documents: HashMap<String, Document>,
+}
+
+impl Server<'_> {
+    fn handle(&mut self, line: &str) -> Option<Response> {
+        let request: Request = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(err) => return Some(response(Value::Null, Err(rpc_error(PARSE_ERROR, err)))),
+        };
+        let result = self.call(&request.method, request.params);
+        Some(response(request.id?, result))
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let result = match method {
+            "format" => serde_json::to_value(self.format(params_of(params)?, true)),
+            "check" => serde_json::to_value(self.format(params_of(params)?, false)),
+            "diagnostics" => match self.diagnostics(params_of(params)?) {
+                Ok(report) => serde_json::to_value(report),
+                Err(err) => return Err(rpc_error(INTERNAL_ERROR, err)),
+            },
+            "close" => match params_of(params)?.path {
+                Some(path) => {
+                    self.documents.remove(&path);
+                    Ok(Value::Null)
+                }
+                None => return Err(rpc_error(INVALID_PARAMS, "expected `path`")),
+            },
+            _ => {
+                let message = format!("unknown method {method}");
+                return Err(rpc_error(METHOD_NOT_FOUND, message));
+            }
+        };
+        result.map_err(|err| rpc_error(INTERNAL_ERROR, err))
+    }
+
+    /// Formats the input, reporting the formatted text and edits when `print` is set, or only
+    /// whether it is formatted otherwise.
+    fn format(&mut self, params: Params, print: bool) -> FileReport {
+        let label = params.path.clone().unwrap_or_else(|| "<stdin>".to_string());
+        self.try_format(params, &label, print)
+            .unwrap_or_else(|err| FileReport::error(label, err))
+    }
+
+    fn try_format(&mut self, params: Params, label: &str, print: bool) -> Result<FileReport> {
+        self.with_document(params, |document| {
+            let code = document.text().to_string();
+            if document.settings().excluded {
+                return Ok(FileReport::new(
+                    label.to_string(),
+                    Status::Unchanged,
+                    print.then_some(code),
+                ));
+            }
+            let formatted = document.format()?;
+            let status = if formatted == code {
+                Status::Unchanged
+            } else {
+                Status::Formatted
+            };
+            let mut report = FileReport::new(label.to_string(), status, None);
+            if print {
+                report.edits = minimal_edits(&code, &formatted);
+                report.formatted = Some(formatted);
+            }
+            Ok(report)
+        })
+    }
+
+    /// Reports the syntax errors of the input, without formatting it.
+    fn diagnostics(&mut self, params: Params) -> Result<DiagnosticsReport> {
+        let path = params.path.clone().unwrap_or_else(|| "<stdin>".to_string());
+        let diagnostics = self.with_document(params, |document| Ok(document.diagnostics()))?;
+        Ok(DiagnosticsReport { path, diagnostics })
+    }
+
+    /// Calls `f` with the document of the request's input. The document of a path is kept and
+    /// updated to the request's text and to the settings that apply now, as configuration files
+    /// may have changed since the last request.
+    fn with_document<T>(
+        &mut self,
+        params: Params,
+        f: impl FnOnce(&mut Document) -> Result<T>,
+    ) -> Result<T> {
+        let text = match (params.text, &params.path) {
+            (Some(text), _) => text,
+            (None, Some(path)) => read_to_string(&mut std::fs::File::open(path)?, None)?.0,
+            (None, None) => return Err(anyhow!("expected `path` or `text`")),
+        };
+        let settings = Settings::resolve(self.options, params.path.as_deref().map(Path::new))?;
+        let Some(path) = params.path else {
+            return f(&mut Document::new(&text, settings)?);
+        };
+        let document = match self.documents.entry(path) {
+            Entry::Occupied(entry) => {
+                let document = entry.into_mut();
+                document.set_text(text)?;
+                if *document.settings() != settings {
+                    document.set_settings(settings);
+                }
+                document
+            }
+            Entry::Vacant(entry) => entry.insert(Document::new(&text, settings)?),
+        };
+        f(document)
+    }
+}
+
+fn params_of(params: Value) -> Result<Params, RpcError> {
+    let params: Params = if params.is_null() {
+        Params::default()
+    } else {
+        serde_json::from_value(params).map_err(|err| rpc_error(INVALID_PARAMS, err))?
+    };
+    if params.path.is_none() && params.text.is_none() {
+        return Err(rpc_error(INVALID_PARAMS, "expected `path` or `text`"));
+    }
+    Ok(params)
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Response {
+    let (result, error) = match result {
+        Ok(result) => (Some(result), None),
+        Err(error) => (None, Some(error)),
+    };
+    Response {
+        jsonrpc: "2.0",
+        id,
+        result,
+        error,
+    }
+}
+
+fn rpc_error(code: i64, message: impl ToString) -> RpcError {
+    RpcError {
+        code,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ledger_beautifier::Parser;
+    use serde_json::json;
+
+    fn call(server: &mut Server, request: Value) -> Value {
+        let response = server.handle(&request.to_string()).expect("a response");
+        serde_json::to_value(response).unwrap()
+    }
+
+    fn error_code(response: &Value) -> i64 {
+        response["error"]["code"].as_i64().expect("an error")
+    }
+
+    #[test]
+    fn answers_framing_errors() {
+        let options = Arguments::parse_from(["ledger-beautifier", "serve"]);
+        let mut server = Server {
+            options: &options,
+            documents: HashMap::new(),
+        };
+        let response = serde_json::to_value(server.handle("{not json").unwrap()).unwrap();
+        assert_eq!(response["jsonrpc"], "2.0");
+        assert_eq!(response["id"], Value::Null);
+        assert_eq!(error_code(&response), PARSE_ERROR);
+
+        let response = call(
+            &mut server,
+            json!({"jsonrpc": "2.0", "id": 1, "method": "bogus"}),
+        );
+        assert_eq!(response["id"], 1);
+        assert_eq!(error_code(&response), METHOD_NOT_FOUND);
+
+        let request = json!({"jsonrpc": "2.0", "id": "a", "method": "format"});
+        let response = call(&mut server, request);
+        assert_eq!(response["id"], "a");
+        assert_eq!(error_code(&response), INVALID_PARAMS);
+
+        let request = json!({"id": 2, "method": "check", "params": {"file": "x.ledger"}});
+        assert_eq!(error_code(&call(&mut server, request)), INVALID_PARAMS);
+
+        let request = json!({"id": 3, "method": "close", "params": {"text": ""}});
+        assert_eq!(error_code(&call(&mut server, request)), INVALID_PARAMS);
+
+        let request = json!({"method": "bogus", "params": {"text": ""}});
+        assert!(server.handle(&request.to_string()).is_none());
+    }
+
+    #[test]
+    fn formats_and_forgets_documents() {
+        let options = Arguments::parse_from(["ledger-beautifier", "serve"]);
+        let mut server = Server {
+            options: &options,
+            documents: HashMap::new(),
+        };
+        let text = "2024/01/01 Shop\n    Expenses:Food    10 EUR\n    Assets:Cash\n";
+        let params = json!({"path": "a.ledger", "text": text});
+        let request = json!({"id": 1, "method": "format", "params": params});
+        let response = call(&mut server, request);
+        assert_eq!(response["result"]["status"], "formatted");
+        let formatted = response["result"]["formatted"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert!(server.documents.contains_key("a.ledger"));
+
+        let params = json!({"path": "a.ledger", "text": formatted});
+        let response = call(
+            &mut server,
+            json!({"id": 2, "method": "check", "params": params}),
+        );
+        assert_eq!(response["result"]["status"], "unchanged");
+
+        let params = json!({"path": "a.ledger"});
+        let response = call(
+            &mut server,
+            json!({"id": 3, "method": "close", "params": params}),
+        );
+        assert_eq!(response["result"], Value::Null);
+        assert!(server.documents.is_empty());
+    }
+
+    #[test]
+    fn does_not_keep_documents_without_a_path() {
+        let options = Arguments::parse_from(["ledger-beautifier", "serve"]);
+        let mut server = Server {
+            options: &options,
+            documents: HashMap::new(),
+        };
+        for (id, text) in [(1, "2024/01/01 Shop\n"), (2, "2024/01/02 Bank\n")] {
+            let params = json!({"text": text});
+            let response = call(
+                &mut server,
+                json!({"id": id, "method": "format", "params": params}),
+            );
+            assert_eq!(response["result"]["path"], "<stdin>");
+            assert_eq!(response["result"]["formatted"], text);
+        }
+        assert!(server.documents.is_empty());
+    }
+
+    #[test]
+    fn follows_configuration_changes() {
+        let options = Arguments::parse_from(["ledger-beautifier", "serve"]);
+        let mut server = Server {
+            options: &options,
+            documents: HashMap::new(),
+        };
+        let dir =
+            std::env::temp_dir().join(format!("ledger-beautifier-serve-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join(".ledger-beautifier.toml");
+        let path = dir.join("a.ledger").to_string_lossy().to_string();
+        let text = "2024/01/01 Shop\n  Expenses:Food  10 EUR\n  Assets:Cash\n";
+        let mut indents = vec![];
+        for width in [2, 4] {
+            std::fs::write(&config, format!("root = true\nindent-width = {width}\n")).unwrap();
+            let params = json!({"path": path, "text": text});
+            let response = call(
+                &mut server,
+                json!({"id": width, "method": "format", "params": params}),
+            );
+            let formatted = response["result"]["formatted"].as_str().unwrap();
+            let posting = formatted.lines().nth(1).unwrap();
+            indents.push(posting.len() - posting.trim_start().len());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(indents, [2, 4]);
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let options = Arguments::parse_from(["ledger-beautifier", "serve"]);
+        let mut server = Server {
+            options: &options,
+            documents: HashMap::new(),
+        };
+        let params =
+            json!({"path": "a.ledger", "text": "2024/01/01 Shop\n    Assets:Cash  == $ 10\n"});
+        let response = call(
+            &mut server,
+            json!({"id": 1, "method": "diagnostics", "params": params}),
+        );
+        assert_eq!(response["result"]["path"], "a.ledger");
+        let diagnostics = response["result"]["diagnostics"].as_array().unwrap();
+        assert!(!diagnostics.is_empty());
+        assert_eq!(diagnostics[0]["severity"], "error");
+        assert_eq!(diagnostics[0]["start"]["line"], 1);
+        assert!(response["result"].get("formatted").is_none());
+
+        // The document follows the text, and the errors go away once it is fixed.
+        let params =
+            json!({"path": "a.ledger", "text": "2024/01/01 Shop\n    Assets:Cash  $ 10\n"});
+        let response = call(
+            &mut server,
+            json!({"id": 2, "method": "diagnostics", "params": params}),
+        );
+        assert_eq!(response["result"]["diagnostics"], json!([]));
+    }
+}
@@ -6,20 +6,248 @@
 
 pub use clap::CommandFactory;
 pub use clap::Parser;
+pub use clap::Subcommand;
+pub use clap::ValueEnum;
 
 static LONG_ABOUT: &str = "
 ledger-beautifier formats and beautifies ledger files.
 
-This beautifier is quite opinionated and does not offer options.";
+Formatting can be tuned per repository with a .ledger-beautifier.toml file, which is searched for in
+the directory of each file and its parents. Command line options override it.";
 
 #[derive(Debug, Parser)]
 #[command(author, version, about = LONG_ABOUT)]
 pub struct Arguments {
-    /// File(s) to beautify. If more than one file is passed, inline is implied. If no file is given, reads from stdin.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// File(s) to beautify. If more than one file is passed, inline is implied unless --output-dir is given. If no file is given, reads from stdin.
     #[arg(global = true)]
     pub files: Vec<String>,
 
     /// Whether files should be formatted inplace instead of printing to stdout.
     #[arg(global = true, long = "inplace")]
     pub inplace: bool,
+
+    /// Writes the formatted output to this file instead of stdout. Accepts at most one input file.
+    #[arg(
+        global = true,
+        short = 'o',
+        long = "output",
+        value_name = "FILE",
+        conflicts_with_all = ["inplace", "output_dir"]
+    )]
+    pub output: Option<String>,
+
+    /// Writes the formatted files into this directory, keeping their paths relative to the working
+    /// directory. Files outside the working directory are rejected.
+    #[arg(
+        global = true,
+        long = "output-dir",
+        value_name = "DIR",
+        conflicts_with = "inplace"
+    )]
+    pub output_dir: Option<String>,
+
+    /// Reads the input as a JSON journal, in the format produced by `export --format json`, and prints it as formatted ledger text.
+    #[arg(global = true, long = "from-json")]
+    pub from_json: bool,
+
+    /// Path of the file whose contents are piped through stdin. It is used to label diagnostics and to find the configuration that applies to it.
+    #[arg(
+        global = true,
+        long = "stdin-filepath",
+        value_name = "PATH",
+        conflicts_with = "files"
+    )]
+    pub stdin_filepath: Option<String>,
+
+    /// Line ending used in the output. `auto` keeps the dominant line ending of the input. [default: auto]
+    #[arg(global = true, long = "line-ending", value_enum)]
+    pub line_ending: Option<LineEnding>,
+
+    /// A preset of formatting options matching a common house style. The other options override it. [default: default]
+    #[arg(global = true, long = "style", value_enum)]
+    pub style: Option<Style>,
+
+    /// The ledger flavour the files are written in. Besides the dates of hledger's `date:` tags, it
+    /// only changes how amounts are read when exporting, converting and importing. [default: ledger]
+    #[arg(global = true, long = "dialect", value_enum)]
+    pub dialect: Option<Dialect>,
+
+    /// Column at which amounts are aligned. [default: 60]
+    #[arg(global = true, long = "alignment-column", value_name = "COLUMN")]
+    pub alignment_column: Option<usize>,
+
+    /// Number of spaces used for each indentation level. [default: 2]
+    #[arg(global = true, long = "indent-width", value_name = "WIDTH")]
+    pub indent_width: Option<usize>,
+
+    /// Whether amounts are aligned at a fixed column or within each transaction. [default: column]
+    #[arg(global = true, long = "amount-alignment", value_enum)]
+    pub amount_alignment: Option<AmountAlignment>,
+
+    /// Whether indentation uses spaces or tabs. [default: space]
+    #[arg(global = true, long = "indent-style", value_enum)]
+    pub indent_style: Option<IndentStyle>,
+
+    /// Whether the output ends with a line break. [default: true]
+    #[arg(global = true, long = "final-newline", value_name = "BOOL")]
+    pub final_newline: Option<bool>,
+
+    /// Whether transactions are sorted by date. [default: true]
+    #[arg(global = true, long = "sort", value_name = "BOOL")]
+    pub sort: Option<bool>,
+
+    /// Where the commodity is written relative to the quantity. [default: after]
+    #[arg(global = true, long = "commodity-position", value_enum)]
+    pub commodity_position: Option<CommodityPosition>,
+
+    /// How transaction dates are written. [default: preserve]
+    #[arg(global = true, long = "date-format", value_enum)]
+    pub date_format: Option<DateFormat>,
+
+    /// Byte offset of the cursor in the input. Where it ends up in the formatted output is printed on stderr, or reported as `cursor_offset` with `--message-format json`.
+    #[arg(global = true, long = "cursor-offset", value_name = "N")]
+    pub cursor_offset: Option<usize>,
+
+    /// Formats only the transaction or directive at this byte offset of the input, or the one ending right before it, and keeps the rest of the input as it is.
+    #[arg(
+        global = true,
+        long = "format-at",
+        value_name = "N",
+        conflicts_with = "cursor_offset"
+    )]
+    pub format_at: Option<usize>,
+
+    /// Prints the settings that apply to each input, after reading configuration files, and exits.
+    #[arg(global = true, long = "print-config")]
+    pub print_config: bool,
+
+    /// How results and errors are reported.
+    #[arg(global = true, long = "message-format", value_enum, default_value_t = MessageFormat::Human)]
+    pub message_format: MessageFormat,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Prints the journal as structured data instead of formatting it. Each input is printed as a separate document.
+    Export {
+        /// Format of the exported data.
+        #[arg(long = "format", value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Field separator of the csv and tsv formats. Must be a single ASCII character. [default: `,` for csv, tab for tsv]
+        #[arg(long = "delimiter")]
+        delimiter: Option<char>,
+    },
+    /// Rewrites the journal in the syntax of another accounting tool. What cannot be expressed in it is reported as warnings.
+    Convert {
+        /// Syntax to convert the journal to.
+        #[arg(long = "to", value_enum)]
+        to: ConvertFormat,
+    },
+    /// Reads bank statements in CSV and prints them as formatted transactions, one per row.
+    Import {
+        /// TOML file describing the columns of the statements and how to assign accounts.
+        #[arg(long = "rules")]
+        rules: String,
+        /// Journal the statements are imported into. Transactions it already has are skipped.
+        #[arg(long = "journal")]
+        journal: Option<String>,
+    },
+    /// Runs a language server over stdio, giving editors formatting, range formatting and diagnostics. The other options apply to every document.
+    Lsp,
+    /// Answers JSON-RPC requests read one per line from stdin, to format, check and diagnose many inputs without starting a process for each. The other options apply to every request.
+    Serve,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Transactions, postings, directives and comments, with their line numbers.
+    Json,
+    /// One row per posting of the dated transactions, with a header row.
+    Csv,
+    /// Like csv, separated by tabs.
+    Tsv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConvertFormat {
+    /// The syntax of Beancount, as read by Fava.
+    Beancount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LineEnding {
+    /// Keeps the line ending that is most common in the input.
+    Auto,
+    /// Unix line endings (`\n`).
+    Lf,
+    /// Windows line endings (`\r\n`).
+    Crlf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Style {
+    /// Amounts at column 60, two spaces of indentation and commodities after quantities.
+    Default,
+    /// Like Emacs' ledger-mode: amounts at column 52 and four spaces of indentation.
+    LedgerMode,
+    /// Like `hledger print`: four spaces of indentation and amounts aligned within each transaction.
+    HledgerPrint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Dialect {
+    /// ledger-cli.
+    Ledger,
+    /// hledger: `decimal-mark` directives are read when exporting, converting and importing, and the
+    /// dates of `date:` and `date2:` tags are formatted.
+    Hledger,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AmountAlignment {
+    /// Aligns amounts at `alignment-column`.
+    Column,
+    /// Aligns amounts two spaces after the longest account of each transaction.
+    Transaction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IndentStyle {
+    /// Indents with `indent-width` spaces per level.
+    Space,
+    /// Indents with one tab per level.
+    Tab,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CommodityPosition {
+    /// `10.00 EUR`
+    After,
+    /// `EUR 10.00`, or `$10.00` for single-character symbols.
+    Before,
+    /// Keeps the commodity where it was written.
+    Preserve,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DateFormat {
+    /// Keeps dates as they were written.
+    Preserve,
+    /// `2024-01-31`
+    Iso,
+    /// `2024/01/31`
+    Slash,
+    /// `2024.01.31`
+    Dot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MessageFormat {
+    /// Colored messages meant to be read by people.
+    Human,
+    /// One JSON object per file, meant to be read by tools.
+    Json,
 }
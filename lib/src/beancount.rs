@@ -0,0 +1,492 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+
+use super::args::DateFormat;
+use super::beautifier::format_date;
+use super::config::Settings;
+use super::diagnostics::{line_warning, Diagnostic};
+use super::journal::{
+    parse_journal, Amount, Directive, Item, Posting, PriceKind, Transaction, TransactionKind,
+};
+use anyhow::Result;
+use chrono::NaiveDate;
+
+/// The result of converting a journal to another syntax.
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    pub text: String,
+    /// What was dropped or changed because the target syntax cannot express it.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The root accounts Beancount accepts.
+const ROOT_ACCOUNTS: [&str; 5] = ["Assets", "Liabilities", "Equity", "Income", "Expenses"];
+
+/// Converts the ledger journal in `code` to Beancount.
+pub fn convert_to_beancount(code: &str, settings: &Settings) -> Result<Conversion> {
+    let journal = parse_journal(code, settings)?;
+    let mut converter = Converter {
+        code,
+        text: String::new(),
+        diagnostics: vec![],
+        year: None,
+        // Beancount needs a date for `open` and `commodity`, use the first one of the journal.
+        first_date: journal
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Transaction(transaction) => transaction.date.as_deref(),
+                _ => None,
+            })
+            .filter_map(|date| iso_date(date, None))
+            .min()
+            .unwrap_or_else(|| "1970-01-01".to_string()),
+        declared: HashSet::new(),
+        used: BTreeMap::new(),
+    };
+    for item in &journal.items {
+        match item {
+            Item::Transaction(transaction) => converter.transaction(transaction)?,
+            Item::Directive(directive) => converter.directive(directive)?,
+            Item::Comment(comment) => {
+                for line in comment.text.lines() {
+                    writeln!(converter.text, "{}", as_comment(line))?;
+                }
+            }
+        }
+        writeln!(converter.text)?;
+    }
+    // Beancount rejects postings to accounts that were never opened.
+    let mut opens = String::new();
+    for (account, date) in &converter.used {
+        if !converter.declared.contains(account) {
+            writeln!(opens, "{date} open {account}")?;
+        }
+    }
+    if !opens.is_empty() {
+        opens += "\n";
+    }
+    Ok(Conversion {
+        text: opens + &converter.text,
+        diagnostics: converter.diagnostics,
+    })
+}
+
+struct Converter<'a> {
+    code: &'a str,
+    text: String,
+    diagnostics: Vec<Diagnostic>,
+    /// The year set by the last `year` directive, for dates written without one.
+    year: Option<String>,
+    first_date: String,
+    /// The accounts of `account` directives, which are opened where they are declared.
+    declared: HashSet<String>,
+    /// The date each account is first posted to.
+    used: BTreeMap<String, String>,
+}
+
+impl Converter<'_> {
+    fn warn(&mut self, line: Option<usize>, message: String, hint: Option<&str>) {
+        self.diagnostics.push(line_warning(
+            self.code,
+            line.unwrap_or_default(),
+            message,
+            hint.map(str::to_string),
+        ));
+    }
+
+    fn transaction(&mut self, transaction: &Transaction) -> Result<()> {
+        if transaction.kind != TransactionKind::Plain {
+            let kind = match transaction.kind {
+                TransactionKind::Periodic => "periodic",
+                _ => "automated",
+            };
+            self.warn(
+                transaction.line,
+                format!("{kind} transactions cannot be converted"),
+                Some("Beancount has no equivalent, the transaction is left as a comment"),
+            );
+            return self.comment_out(transaction);
+        }
+        let written = transaction.date.as_deref().unwrap_or_default();
+        let Some(date) = iso_date(written, self.year.as_deref()) else {
+            self.warn(
+                transaction.line,
+                format!(
+                    "cannot convert the date `{written}`, the transaction is left as a comment"
+                ),
+                Some("Beancount dates are written YYYY-MM-DD, add a `year` directive"),
+            );
+            return self.comment_out(transaction);
+        };
+        let flag = match transaction.status.as_deref() {
+            Some("!") => "!",
+            Some(_) => "*",
+            None => "txn",
+        };
+        write!(self.text, "{date} {flag}")?;
+        if let Some(payee) = &transaction.payee {
+            write!(self.text, " {}", quote(payee))?;
+        }
+        writeln!(self.text)?;
+        if let Some(code) = &transaction.code {
+            let code = code.trim_start_matches('(').trim_end_matches(')');
+            writeln!(self.text, "  code: {}", quote(code))?;
+        }
+        if let Some(effective_date) = &transaction.effective_date {
+            match iso_date(effective_date, Some(&date[..4])) {
+                Some(effective_date) => writeln!(self.text, "  effective-date: {effective_date}")?,
+                None => self.warn(
+                    transaction.line,
+                    format!("cannot convert the effective date `{effective_date}`"),
+                    None,
+                ),
+            }
+        }
+        for note in &transaction.notes {
+            writeln!(self.text, "  {}", as_comment(note))?;
+        }
+        let mut assertions = vec![];
+        for posting in &transaction.postings {
+            if let Some(assertion) = self.posting(posting, &date)? {
+                assertions.push(assertion);
+            }
+        }
+        for (account, amount) in assertions {
+            // Beancount checks balances at the start of the day, ledger after the posting.
+            match next_day(&date) {
+                Some(day) => writeln!(self.text, "{day} balance {account} {amount}")?,
+                None => self.warn(
+                    transaction.line,
+                    format!("cannot date the balance assertion of `{account}`"),
+                    None,
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    fn comment_out(&mut self, transaction: &Transaction) -> Result<()> {
+        for line in transaction.to_string().lines() {
+            writeln!(self.text, "; {line}")?;
+        }
+        Ok(())
+    }
+
+    /// Writes `posting` of a transaction of `date`, returning the account and amount of its
+    /// balance assertion, if any.
+    fn posting(&mut self, posting: &Posting, date: &str) -> Result<Option<(String, String)>> {
+        let account = posting.account.as_str();
+        if account.starts_with('(') || account.starts_with('[') {
+            self.warn(
+                posting.line,
+                format!("virtual posting to `{account}` cannot be converted"),
+                Some("Beancount postings must balance, the posting is left as a comment"),
+            );
+            writeln!(self.text, "  ; {}", posting.to_string().trim())?;
+            return Ok(None);
+        }
+        let account = self.account(account, posting.line);
+        match self.used.get_mut(&account) {
+            Some(first) if date < first.as_str() => *first = date.to_string(),
+            Some(_) => {}
+            None => {
+                self.used.insert(account.clone(), date.to_string());
+            }
+        }
+        write!(self.text, "  ")?;
+        if posting.status.as_deref() == Some("!") {
+            write!(self.text, "! ")?;
+        }
+        write!(self.text, "{account}")?;
+        if let Some(amount) = &posting.amount {
+            let amount = self.amount(amount, posting.line);
+            write!(self.text, "  {amount}")?;
+        }
+        if let Some(cost) = &posting.cost {
+            let amount = self.amount(&cost.amount, posting.line);
+            match cost.kind {
+                PriceKind::Unit => write!(self.text, " {{{amount}}}")?,
+                PriceKind::Total => write!(self.text, " {{{{{amount}}}}}")?,
+            }
+        }
+        if let Some(price) = &posting.price {
+            let amount = self.amount(&price.amount, posting.line);
+            match price.kind {
+                PriceKind::Unit => write!(self.text, " @ {amount}")?,
+                PriceKind::Total => write!(self.text, " @@ {amount}")?,
+            }
+        }
+        let mut notes = posting.notes.iter();
+        if let Some(note) = notes.next() {
+            write!(self.text, "  {}", as_comment(note))?;
+        }
+        writeln!(self.text)?;
+        for note in notes {
+            writeln!(self.text, "    {}", as_comment(note))?;
+        }
+        let Some(assertion) = &posting.balance_assertion else {
+            return Ok(None);
+        };
+        if assertion.operator != "=" {
+            self.warn(
+                posting.line,
+                format!(
+                    "the balance assertion `{}` is converted as `=`",
+                    assertion.operator
+                ),
+                Some("Beancount balance assertions always include subaccounts"),
+            );
+        }
+        let amount = self.amount(&assertion.amount, posting.line);
+        Ok(Some((account, amount)))
+    }
+
+    fn directive(&mut self, directive: &Directive) -> Result<()> {
+        let value = directive.value.as_deref().unwrap_or_default();
+        match directive.kind.as_str() {
+            "account" => {
+                let account = self.account(value, directive.line);
+                writeln!(self.text, "{} open {account}", self.first_date)?;
+                self.declared.insert(account);
+            }
+            "commodity" => {
+                let commodity = self.commodity(value, directive.line);
+                writeln!(self.text, "{} commodity {commodity}", self.first_date)?;
+            }
+            "include" => writeln!(self.text, "include {}", quote(value))?,
+            "year" | "Y" => {
+                self.year = Some(value.to_string());
+                writeln!(self.text, "; {} {value}", directive.kind)?;
+                return Ok(());
+            }
+            kind => {
+                self.warn(
+                    directive.line,
+                    format!("the `{kind}` directive cannot be converted"),
+                    Some("it is left as a comment"),
+                );
+                for line in directive.to_string().lines() {
+                    writeln!(self.text, "; {line}")?;
+                }
+                return Ok(());
+            }
+        }
+        for subdirective in &directive.subdirectives {
+            let value = subdirective.value.as_deref().unwrap_or_default();
+            if subdirective.kind == "note" {
+                writeln!(self.text, "  description: {}", quote(value))?;
+                continue;
+            }
+            self.warn(
+                directive.line,
+                format!(
+                    "the `{}` subdirective cannot be converted",
+                    subdirective.kind
+                ),
+                Some("it is left as a comment"),
+            );
+            writeln!(self.text, "  ; {} {value}", subdirective.kind)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites `account` with the characters Beancount accepts, warning when its root is not one
+    /// of the five Beancount knows.
+    fn account(&mut self, account: &str, line: Option<usize>) -> String {
+        let converted = account
+            .split(':')
+            .map(|component| {
+                let component: String = component
+                    .trim()
+                    .chars()
+                    .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                    .collect();
+                let mut chars = component.chars();
+                match chars.next() {
+                    Some(first) if first.is_alphanumeric() => {
+                        first.to_uppercase().chain(chars).collect()
+                    }
+                    _ => format!("X{component}"),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(":");
+        let root = converted.split(':').next().unwrap_or_default();
+        if !ROOT_ACCOUNTS.contains(&root) {
+            self.warn(
+                line,
+                format!("the account `{account}` does not start with a Beancount root account"),
+                Some(
+                    "Beancount accounts start with Assets, Liabilities, Equity, Income or Expenses",
+                ),
+            );
+        }
+        converted
+    }
+
+    fn amount(&mut self, amount: &Amount, line: Option<usize>) -> String {
+        let quantity = amount.value.clone().unwrap_or_else(|| {
+            self.warn(
+                line,
+                format!("cannot read the quantity `{}`", amount.quantity),
+                None,
+            );
+            amount.quantity.clone()
+        });
+        match &amount.commodity {
+            Some(commodity) => format!("{quantity} {}", self.commodity(commodity, line)),
+            None => {
+                self.warn(
+                    line,
+                    format!("the amount `{quantity}` has no commodity"),
+                    Some("Beancount amounts need a currency"),
+                );
+                quantity
+            }
+        }
+    }
+
+    /// Maps currency symbols to their ISO code and rewrites other commodities in uppercase, the way
+    /// Beancount expects them.
+    fn commodity(&mut self, commodity: &str, line: Option<usize>) -> String {
+        let commodity = commodity.trim_matches('"');
+        let converted = match commodity {
+            "$" => "USD".to_string(),
+            "€" => "EUR".to_string(),
+            "£" => "GBP".to_string(),
+            "¥" => "JPY".to_string(),
+            _ => commodity
+                .chars()
+                .map(|c| match c {
+                    c if c.is_ascii_alphanumeric() || "'._-".contains(c) => c.to_ascii_uppercase(),
+                    _ => '-',
+                })
+                .collect(),
+        };
+        if !converted.starts_with(|c: char| c.is_ascii_uppercase()) || converted.len() > 24 {
+            self.warn(
+                line,
+                format!("the commodity `{commodity}` is not a valid Beancount currency"),
+                Some("Beancount currencies are up to 24 uppercase letters, starting with a letter"),
+            );
+        }
+        converted
+    }
+}
+
+/// Rewrites `date` as `YYYY-MM-DD`, taking the year from `year` when it has none.
+fn iso_date(date: &str, year: Option<&str>) -> Option<String> {
+    let date = format_date(date, DateFormat::Iso);
+    let date = match date.matches('-').count() {
+        1 => format!("{}-{date}", year?),
+        _ => date,
+    };
+    let valid = date.len() == 10
+        && date.chars().enumerate().all(|(i, c)| {
+            if i == 4 || i == 7 {
+                c == '-'
+            } else {
+                c.is_ascii_digit()
+            }
+        });
+    let exists = NaiveDate::parse_from_str(&date, "%Y-%m-%d").is_ok();
+    (valid && exists).then_some(date)
+}
+
+/// The day after the `YYYY-MM-DD` date `date`.
+fn next_day(date: &str) -> Option<String> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()?
+        .succ_opt()?;
+    Some(date.format("%Y-%m-%d").to_string())
+}
+
+/// Rewrites a ledger comment or note so it starts with `;`, the only comment character of
+/// Beancount.
+fn as_comment(text: &str) -> String {
+    match text.trim().strip_prefix(['#', '%', '|', '*']) {
+        Some(rest) => format!(";{rest}"),
+        None if text.trim().starts_with(';') => text.trim().to_string(),
+        None => format!("; {}", text.trim()),
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_balances_on_the_next_day() {
+        assert_eq!(next_day("2024-01-31").as_deref(), Some("2024-02-01"));
+        assert_eq!(next_day("2024-02-28").as_deref(), Some("2024-02-29"));
+        assert_eq!(next_day("2023-02-28").as_deref(), Some("2023-03-01"));
+        assert_eq!(next_day("2024-12-31").as_deref(), Some("2025-01-01"));
+        assert_eq!(next_day("2024-02-30"), None);
+    }
+
+    #[test]
+    fn converts_dates_to_iso() {
+        assert_eq!(iso_date("2024/03/01", None).as_deref(), Some("2024-03-01"));
+        assert_eq!(
+            iso_date("03/01", Some("2024")).as_deref(),
+            Some("2024-03-01")
+        );
+        assert_eq!(iso_date("03/01", None), None);
+        assert_eq!(iso_date("2023/02/29", None), None);
+    }
+
+    #[test]
+    fn converts_journals() {
+        let code = "account Assets:Bank
+commodity EUR
+
+2024/01/06 Broker
+    Assets:Stocks    2 AAPL @ 150 USD
+    Assets:Stocks    1 AAPL {140 USD} @@ 150 USD
+    Assets:Bank
+
+2024/01/05 * (42) Shop
+    Expenses:Food    10 EUR
+    Assets:Cash    -10 EUR = 90 EUR
+
+01/07 Undated
+    Expenses:Food    5 EUR
+    Assets:Cash
+";
+        let conversion = convert_to_beancount(code, &Settings::default()).unwrap();
+        let text = conversion.text;
+        for expected in [
+            "2024-01-05 open Assets:Cash\n",
+            "2024-01-05 open Expenses:Food\n",
+            "2024-01-06 open Assets:Stocks\n",
+            "2024-01-05 open Assets:Bank\n",
+            "2024-01-05 commodity EUR\n",
+            "2024-01-06 txn \"Broker\"\n",
+            "  Assets:Stocks  2 AAPL @ 150 USD\n",
+            "  Assets:Stocks  1 AAPL {140 USD} @@ 150 USD\n",
+            "2024-01-05 * \"Shop\"\n  code: \"42\"\n",
+            "  Assets:Cash  -10 EUR\n",
+            "2024-01-06 balance Assets:Cash 90 EUR\n",
+            "; 01/07 Undated\n",
+        ] {
+            assert!(text.contains(expected), "{expected:?} in\n{text}");
+        }
+        assert_eq!(text.matches("open Assets:Bank").count(), 1);
+        assert_eq!(conversion.diagnostics.len(), 1);
+        let warning = &conversion.diagnostics[0];
+        assert!(warning
+            .message
+            .starts_with("cannot convert the date `01/07`"));
+        assert_eq!(warning.start.line, 12);
+    }
+}
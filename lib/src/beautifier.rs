@@ -3,29 +3,99 @@
  * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::borrow::Cow;
 use std::cmp::max;
+use std::collections::HashMap;
+use std::ops::Range;
 
-use super::args::Arguments;
+use super::args::{
+    AmountAlignment, CommodityPosition, DateFormat, Dialect, IndentStyle, LineEnding,
+};
+use super::config::Settings;
+use super::diagnostics::{collect_diagnostics, ParseError};
+use super::edits::{minimal_edits, TextEdit};
 use anyhow::{anyhow, Context, Result};
 use tree_sitter::Node;
 use unicode_segmentation::UnicodeSegmentation;
 
 struct State<'a> {
     formatted: String,
-    arguments: &'a mut Arguments,
+    settings: &'a Settings,
     code: &'a [u8],
     col: usize,
     row: usize,
     level: usize,
     extra_indentation: usize,
-    num_spaces: usize,
     consecutive_ln_count: usize,
+    newline: &'static str,
+    /// The column amounts are aligned at in the current transaction.
+    alignment_column: usize,
+    /// Where the source text that is printed as is ends up in `formatted`, when it is tracked.
+    spans: Option<Vec<Span>>,
+    /// The output of items formatted before, when formatting the same document repeatedly.
+    cache: Option<&'a mut ItemCache>,
+}
+
+/// The formatted output of the top-level items of a document, so that formatting it again only
+/// formats the items that changed.
+#[derive(Default)]
+pub(crate) struct ItemCache {
+    /// What the last run formatted.
+    previous: HashMap<ItemKey, ItemOutput>,
+    /// What this run formatted or took from `previous`.
+    current: HashMap<ItemKey, ItemOutput>,
+}
+
+/// An item and the state it is formatted in, which is all its output depends on.
+#[derive(PartialEq, Eq, Hash)]
+struct ItemKey {
+    text: String,
+    consecutive_ln_count: usize,
+    col: usize,
+    newline: &'static str,
+}
+
+#[derive(Clone)]
+struct ItemOutput {
+    text: String,
+    consecutive_ln_count: usize,
+    col: usize,
+    rows: usize,
+}
+
+impl ItemCache {
+    /// Forgets the items that were not used since the last call, and starts a new run.
+    pub(crate) fn finish_run(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    fn get(&mut self, key: ItemKey) -> Option<ItemOutput> {
+        if let Some(output) = self.current.get(&key) {
+            return Some(output.clone());
+        }
+        let output = self.previous.remove(&key)?;
+        self.current.insert(key, output.clone());
+        Some(output)
+    }
+}
+
+/// A piece of the source copied to the output.
+struct Span {
+    source: Range<usize>,
+    output: Range<usize>,
 }
 
 impl State<'_> {
     fn indent(&mut self) {
         for _ in 0..self.level {
-            self.print(" ".repeat(self.num_spaces).as_str());
+            match self.settings.indent_style {
+                IndentStyle::Space => self.print(" ".repeat(self.settings.indent_width).as_str()),
+                IndentStyle::Tab => {
+                    self.print("\t");
+                    // Tabs are as wide as an indentation level when aligning amounts.
+                    self.col += self.settings.indent_width.saturating_sub(1);
+                }
+            }
         }
         for _ in 0..self.extra_indentation {
             self.print(" ");
@@ -33,27 +103,74 @@ impl State<'_> {
     }
 
     fn print(&mut self, string: &str) {
-        if self.arguments.inplace {
-            self.formatted += string;
-        } else {
-            print!("{}", string);
-        }
+        self.track(string);
+        let string = normalize_newlines(string, self.newline);
+        let string = string.as_ref();
+        self.formatted += string;
         self.consecutive_ln_count = 0;
         self.col += string.graphemes(true).count();
     }
 
+    /// Prints source text that is not formatted, keeping track of the lines it ends with.
+    fn print_verbatim(&mut self, string: &str) {
+        self.print(string);
+        let trimmed = string.trim_end_matches(['\r', '\n']);
+        self.consecutive_ln_count = string[trimmed.len()..].matches('\n').count();
+        self.row += string.matches('\n').count();
+        if let Some(last_line) = string.rfind('\n').map(|i| &string[i + 1..]) {
+            self.col = last_line.graphemes(true).count();
+        }
+    }
+
     fn print_node(&mut self, node: Node) -> Result<()> {
         self.print(node.utf8_text(self.code)?);
         Ok(())
     }
 
-    fn println(&mut self, string: &str) {
-        if self.arguments.inplace {
-            self.formatted += string;
-            self.formatted += "\n";
-        } else {
-            println!("{}", string);
+    /// Records where `string` comes from, if it is a slice of the source. Must be called before
+    /// `string` is printed, and maps it as `normalize_newlines` converts it.
+    fn track(&mut self, string: &str) {
+        let Some(spans) = &mut self.spans else {
+            return;
+        };
+        let start = (string.as_ptr() as usize).wrapping_sub(self.code.as_ptr() as usize);
+        if string.is_empty() || start.saturating_add(string.len()) > self.code.len() {
+            return;
         }
+        let mut source = start;
+        let mut output = self.formatted.len();
+        let mut rest = string;
+        while !rest.is_empty() {
+            let len = rest.find(['\r', '\n']).unwrap_or(rest.len());
+            if len > 0 {
+                spans.push(Span {
+                    source: source..source + len,
+                    output: output..output + len,
+                });
+            }
+            rest = &rest[len..];
+            // Line breaks become `newline`, and stray carriage returns are dropped.
+            let (source_len, output_len) = if rest.starts_with("\r\n") {
+                (2, self.newline.len())
+            } else if rest.starts_with('\n') {
+                (1, self.newline.len())
+            } else if rest.starts_with('\r') {
+                (1, 0)
+            } else {
+                (0, 0)
+            };
+            rest = &rest[source_len..];
+            source += len + source_len;
+            output += len + output_len;
+        }
+    }
+
+    fn println(&mut self, string: &str) {
+        self.track(string);
+        let string = normalize_newlines(string, self.newline);
+        let string = string.as_ref();
+        self.formatted += string;
+        self.formatted += self.newline;
         self.consecutive_ln_count += 1;
         self.col = 0;
         self.row += 1;
@@ -66,7 +183,7 @@ impl State<'_> {
     }
 }
 
-trait TraversingError<T> {
+pub(crate) trait TraversingError<T> {
     fn err_at_loc(self, node: &Node) -> Result<T>;
 }
 
@@ -82,55 +199,389 @@ impl<T> TraversingError<T> for Option<T> {
     }
 }
 
-pub fn beautify(code: &str, arguments: &mut Arguments) -> Result<String> {
+impl LineEnding {
+    /// Returns the line ending to be used when formatting `code`.
+    pub fn resolve(self, code: &str) -> &'static str {
+        match self {
+            LineEnding::Auto => detect_line_ending(code),
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+/// Returns the dominant line ending of `code`. Defaults to `\n` on ties.
+pub fn detect_line_ending(code: &str) -> &'static str {
+    let crlf = code.matches("\r\n").count();
+    let lf = code.matches('\n').count() - crlf;
+    if crlf > lf {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+/// Rewrites every line break in `string` to `newline`, dropping stray carriage returns.
+fn normalize_newlines<'a>(string: &'a str, newline: &str) -> Cow<'a, str> {
+    if !string.contains('\r') && (newline == "\n" || !string.contains('\n')) {
+        return Cow::Borrowed(string);
+    }
+    let string = string.replace("\r\n", "\n").replace('\r', "");
+    if newline == "\n" {
+        Cow::Owned(string)
+    } else {
+        Cow::Owned(string.replace('\n', newline))
+    }
+}
+
+/// Parses `code`, failing with a `ParseError` if it contains syntax errors.
+pub(crate) fn parse(code: &str) -> Result<tree_sitter::Tree> {
+    let tree = new_parser()?
+        .parse(code, None)
+        .ok_or_else(|| anyhow!("Could not parse file."))?;
+    check_tree(&tree, code)?;
+    Ok(tree)
+}
+
+/// Creates a parser for ledger files.
+pub(crate) fn new_parser() -> Result<tree_sitter::Parser> {
     let mut parser = tree_sitter::Parser::new();
     parser
         .set_language(&tree_sitter_ledger::LANGUAGE.into())
         .with_context(|| "Could not set Tree-Sitter language")?;
+    Ok(parser)
+}
 
-    let tree = parser
-        .parse(code, None)
-        .ok_or_else(|| anyhow!("Could not parse file."))?;
-
+/// Fails with a `ParseError` if `tree` contains syntax errors.
+pub(crate) fn check_tree(tree: &tree_sitter::Tree, code: &str) -> Result<()> {
     let root = tree.root_node();
     if root.has_error() {
-        let error_node = find_first_error_node(root)
-            .ok_or_else(|| anyhow!("An error occurred, but no ERROR node was found."))?;
-        let line = error_node.start_position().row + 1;
-        return Err(anyhow!("Parsed file contain errors (at line {line})."));
+        let diagnostics = collect_diagnostics(root, code);
+        if diagnostics.is_empty() {
+            return Err(anyhow!("An error occurred, but no ERROR node was found."));
+        }
+        return Err(ParseError { diagnostics }.into());
     }
+    Ok(())
+}
+
+pub fn beautify(code: &str, settings: &Settings) -> Result<String> {
+    Ok(format_code(code, settings, false)?.0)
+}
+
+/// Formats `code` like `beautify`, and returns where the byte offset `cursor` of `code` ends up in
+/// the formatted text. A cursor inside text the formatter copies stays next to the same character;
+/// one in whitespace that is rewritten moves to the end of the text before it on the same line, or
+/// else to the start of the text after it.
+pub fn beautify_with_cursor(
+    code: &str,
+    settings: &Settings,
+    cursor: usize,
+) -> Result<(String, usize)> {
+    let (formatted, spans) = format_code(code, settings, true)?;
+    let mut cursor = map_cursor(code, &spans, cursor).min(formatted.len());
+    while !formatted.is_char_boundary(cursor) {
+        cursor -= 1;
+    }
+    Ok((formatted, cursor))
+}
 
+fn map_cursor(code: &str, spans: &[Span], cursor: usize) -> usize {
+    if let Some(span) = spans.iter().find(|span| span.source.contains(&cursor)) {
+        return span.output.start + cursor - span.source.start;
+    }
+    let before = spans
+        .iter()
+        .filter(|span| span.source.end <= cursor)
+        .max_by_key(|span| span.source.end);
+    let after = spans
+        .iter()
+        .filter(|span| span.source.start > cursor)
+        .min_by_key(|span| span.source.start);
+    match (before, after) {
+        (Some(before), _)
+            if !code
+                .get(before.source.end..cursor)
+                .is_some_and(|gap| gap.contains('\n')) =>
+        {
+            before.output.end
+        }
+        (_, Some(after)) => after.output.start,
+        (Some(before), None) => before.output.end,
+        (None, None) => 0,
+    }
+}
+
+/// Formats `code`, also returning the spans of the source copied to the output when `track` is
+/// set.
+fn format_code(code: &str, settings: &Settings, track: bool) -> Result<(String, Vec<Span>)> {
+    let tree = parse(code)?;
+    format_root(code, tree.root_node(), settings, track, None)
+}
+
+/// Formats the already parsed `tree` of `code`, taking the items that did not change from `cache`.
+pub(crate) fn format_tree(
+    code: &str,
+    tree: &tree_sitter::Tree,
+    settings: &Settings,
+    cache: &mut ItemCache,
+) -> Result<String> {
+    check_tree(tree, code)?;
+    let (formatted, _) = format_root(code, tree.root_node(), settings, false, Some(cache))?;
+    cache.finish_run();
+    Ok(formatted)
+}
+
+fn format_root<'a>(
+    code: &'a str,
+    root: Node,
+    settings: &'a Settings,
+    track: bool,
+    cache: Option<&'a mut ItemCache>,
+) -> Result<(String, Vec<Span>)> {
+    let newline = settings.line_ending.resolve(code);
     let mut state = State {
-        arguments,
+        settings,
         code: code.as_bytes(),
         col: 0,
         row: 0,
         level: 0,
         extra_indentation: 0,
         formatted: String::with_capacity(code.len() * 2),
-        num_spaces: 2,
         consecutive_ln_count: 0,
+        newline,
+        alignment_column: settings.alignment_column,
+        spans: track.then(Vec::new),
+        cache,
     };
 
     format_document(&mut state, root)?;
-    Ok(state.formatted)
+    let mut formatted = state.formatted;
+    if settings.final_newline {
+        if !formatted.is_empty() && !formatted.ends_with(newline) {
+            formatted += newline;
+        }
+    } else {
+        formatted.truncate(formatted.trim_end_matches(['\r', '\n']).len());
+    }
+    Ok((formatted, state.spans.unwrap_or_default()))
 }
 
-fn find_first_error_node(node: tree_sitter::Node) -> Option<tree_sitter::Node> {
-    if node.is_error() {
-        return Some(node);
+/// Formats the top-level items of `code` that overlap the byte range `range`, or contain it when it
+/// is empty, without sorting them. The returned edits are minimal, leave everything outside of
+/// those items untouched, and are empty when they are already formatted. Regions protected by
+/// control comments are kept as they are.
+pub fn format_range(code: &str, range: Range<usize>, settings: &Settings) -> Result<Vec<TextEdit>> {
+    let tree = parse(code)?;
+    let (replaced, new_text) = format_items(code, &tree, range, settings)?;
+    let mut edits = minimal_edits(&code[replaced.clone()], &new_text);
+    for edit in &mut edits {
+        edit.range = replaced.start + edit.range.start..replaced.start + edit.range.end;
     }
-    for child in node.children(&mut node.walk()) {
-        if let Some(error_node) = find_first_error_node(child) {
-            return Some(error_node);
+    Ok(edits)
+}
+
+/// Formats only the transaction or directive at the byte `offset` of `code`, or the one that ends
+/// right before it, as when a posting was just typed. Returns the whole item as it is in `code`,
+/// with its line breaks, and its formatted text, or `None` if there is no such item. Items
+/// protected by control comments are returned unchanged.
+pub fn format_item_at(code: &str, offset: usize, settings: &Settings) -> Result<Option<TextEdit>> {
+    if offset > code.len() || !code.is_char_boundary(offset) {
+        return Err(anyhow!("The offset {offset} is not inside the text."));
+    }
+    let tree = parse(code)?;
+    let Some(item) = enclosing_item(tree.root_node(), offset)
+        .or_else(|| enclosing_item(tree.root_node(), offset.checked_sub(1)?))
+    else {
+        return Ok(None);
+    };
+    let start = item.start_byte();
+    let (range, new_text) = format_items(code, &tree, start..start, settings)?;
+    Ok(Some(TextEdit { range, new_text }))
+}
+
+/// The top-level item around the byte `offset` when it is a transaction or a directive.
+fn enclosing_item(root: Node, offset: usize) -> Option<Node> {
+    let mut node = root.descendant_for_byte_range(offset, offset)?;
+    while !matches!(node.kind(), "xact" | "directive") {
+        node = node.parent()?;
+    }
+    let item = node.parent()?;
+    (item.kind() == "journal_item" && item.byte_range().contains(&offset)).then_some(item)
+}
+
+/// Formats the items `format_range` works on. Returns the range of `code` they span, and their
+/// formatted text.
+fn format_items(
+    code: &str,
+    tree: &tree_sitter::Tree,
+    range: Range<usize>,
+    settings: &Settings,
+) -> Result<(Range<usize>, String)> {
+    if range.start > range.end
+        || range.end > code.len()
+        || !code.is_char_boundary(range.start)
+        || !code.is_char_boundary(range.end)
+    {
+        return Err(anyhow!("The range {range:?} is not inside the text."));
+    }
+    let root = tree.root_node();
+    let mut state = State {
+        settings,
+        code: code.as_bytes(),
+        col: 0,
+        row: 0,
+        level: 0,
+        extra_indentation: 0,
+        formatted: String::new(),
+        // The items start a line and keep the line breaks before them, so the first one must not
+        // print the blank line that separates transactions.
+        consecutive_ln_count: 2,
+        newline: settings.line_ending.resolve(code),
+        alignment_column: settings.alignment_column,
+        spans: None,
+        cache: None,
+    };
+    let overlaps = |span: &Range<usize>| {
+        if range.is_empty() {
+            span.start <= range.start && range.start < span.end
+        } else {
+            span.start < range.end && range.start < span.end
+        }
+    };
+    let segments = split_segments(&state, root);
+    let mut replaced: Option<Range<usize>> = None;
+    let mut extend = |span: Range<usize>| {
+        replaced = Some(match &replaced {
+            Some(replaced) => replaced.start.min(span.start)..replaced.end.max(span.end),
+            None => span,
+        });
+    };
+    for segment in &segments {
+        match segment {
+            Segment::Formatted(children) => children
+                .iter()
+                .filter(|child| child.kind() == "journal_item")
+                .map(|child| child.byte_range())
+                .filter(|span| overlaps(span))
+                .for_each(&mut extend),
+            Segment::Verbatim(span) if overlaps(span) => extend(span.clone()),
+            Segment::Verbatim(_) => {}
+        }
+    }
+    let Some(replaced) = replaced else {
+        return Ok((range.clone(), code[range].to_string()));
+    };
+    for segment in segments {
+        match segment {
+            Segment::Formatted(children) => {
+                let children = children
+                    .into_iter()
+                    .filter(|child| {
+                        replaced.start <= child.start_byte() && child.end_byte() <= replaced.end
+                    })
+                    .collect();
+                format_journal_items(&mut state, children)?;
+            }
+            Segment::Verbatim(span) if replaced.start <= span.start && span.end <= replaced.end => {
+                state.print_verbatim(&code[span]);
+            }
+            Segment::Verbatim(_) => {}
         }
     }
-    None
+    // Keep the line breaks the items end with, the ones after them are not replaced.
+    let original = &code[replaced.clone()];
+    let mut formatted = state.formatted;
+    formatted.truncate(formatted.trim_end_matches(['\r', '\n']).len());
+    formatted += &original[original.trim_end_matches(['\r', '\n']).len()..];
+    Ok((replaced, formatted))
+}
+
+/// A run of top-level nodes that are formatted (and sorted) together, or a region of the source
+/// that formatter control comments protect and that is copied as is.
+enum Segment<'a> {
+    Formatted(Vec<Node<'a>>),
+    Verbatim(Range<usize>),
+}
+
+/// A `; ledger-beautifier: <command>` comment.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Control {
+    Off,
+    On,
+    Skip,
 }
 
 fn format_document(state: &mut State, node: Node) -> Result<()> {
+    for segment in split_segments(state, node) {
+        match segment {
+            Segment::Formatted(children) => format_segment(state, children)?,
+            Segment::Verbatim(range) => {
+                let text = std::str::from_utf8(&state.code[range])?;
+                state.print_verbatim(text);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Splits the children of the document at formatter control comments. Everything between
+/// `off` and `on`, or from `skip` to the end of the next item, is kept verbatim.
+fn split_segments<'a>(state: &State, node: Node<'a>) -> Vec<Segment<'a>> {
+    let mut segments = vec![];
+    let mut children = vec![];
+    let mut protected: Option<(Control, usize)> = None;
     let mut cursor = node.walk();
-    let mut children: Vec<Node> = node.children(&mut cursor).collect();
+    for child in node.children(&mut cursor) {
+        let control = control_comment(state, child);
+        match (protected, control) {
+            (None, Some(control @ (Control::Off | Control::Skip))) => {
+                segments.push(Segment::Formatted(std::mem::take(&mut children)));
+                protected = Some((control, child.start_byte()));
+            }
+            (None, _) => children.push(child),
+            (Some((Control::Off, start)), Some(Control::On)) => {
+                segments.push(Segment::Verbatim(start..child.end_byte()));
+                protected = None;
+            }
+            // A `skip` right before an `off` or another `skip` extends to what they protect.
+            (Some((Control::Skip, start)), Some(control @ (Control::Off | Control::Skip))) => {
+                protected = Some((control, start));
+            }
+            (Some((Control::Skip, start)), _) if child.kind() == "journal_item" => {
+                segments.push(Segment::Verbatim(start..child.end_byte()));
+                protected = None;
+            }
+            (Some(_), _) => {}
+        }
+    }
+    match protected {
+        Some((_, start)) => segments.push(Segment::Verbatim(start..node.end_byte())),
+        None => segments.push(Segment::Formatted(children)),
+    }
+    segments
+}
+
+/// Returns the command of a top-level `; ledger-beautifier: off|on|skip` comment.
+fn control_comment(state: &State, node: Node) -> Option<Control> {
+    let comment = node.child(0).filter(|c| c.kind() == "comment")?;
+    let text = comment.utf8_text(state.code).ok()?;
+    let command = text
+        .trim_start_matches([';', '#', '%', '|', '*', ' ', '\t'])
+        .strip_prefix("ledger-beautifier:")?
+        .trim();
+    match command {
+        "off" => Some(Control::Off),
+        "on" => Some(Control::On),
+        "skip" => Some(Control::Skip),
+        _ => None,
+    }
+}
+
+fn format_segment(state: &mut State, mut children: Vec<Node>) -> Result<()> {
+    if !state.settings.sort {
+        return format_journal_items(state, children);
+    }
     children.sort_by_key(|x| {
         if x.kind() == "\n" || x.child(0).unwrap().kind() != "xact" {
             (0, "")
@@ -140,6 +591,10 @@ fn format_document(state: &mut State, node: Node) -> Result<()> {
             (1, date_text)
         }
     });
+    format_journal_items(state, children)
+}
+
+fn format_journal_items(state: &mut State, children: Vec<Node>) -> Result<()> {
     let mut added_newline = false;
     for child in children {
         if child.kind() == "\n" && !added_newline {
@@ -147,12 +602,52 @@ fn format_document(state: &mut State, node: Node) -> Result<()> {
             added_newline = true;
         } else if child.kind() == "journal_item" {
             added_newline = false;
-            format_journal_item(state, child.child(0).err_at_loc(&child)?)?;
+            format_cached_journal_item(state, child.child(0).err_at_loc(&child)?)?;
         }
     }
     Ok(())
 }
 
+/// Formats `node`, or copies its output from the cache when it was already formatted in the same
+/// state.
+fn format_cached_journal_item(state: &mut State, node: Node) -> Result<()> {
+    if state.cache.is_none() {
+        return format_journal_item(state, node);
+    }
+    let key = || -> Result<ItemKey> {
+        Ok(ItemKey {
+            text: node.utf8_text(state.code)?.to_string(),
+            consecutive_ln_count: state.consecutive_ln_count,
+            col: state.col,
+            newline: state.newline,
+        })
+    };
+    if let Some(output) = state
+        .cache
+        .as_mut()
+        .and_then(|cache| cache.get(key().ok()?))
+    {
+        state.formatted += &output.text;
+        state.consecutive_ln_count = output.consecutive_ln_count;
+        state.col = output.col;
+        state.row += output.rows;
+        return Ok(());
+    }
+    let key = key()?;
+    let (start, row) = (state.formatted.len(), state.row);
+    format_journal_item(state, node)?;
+    let output = ItemOutput {
+        text: state.formatted[start..].to_string(),
+        consecutive_ln_count: state.consecutive_ln_count,
+        col: state.col,
+        rows: state.row - row,
+    };
+    if let Some(cache) = state.cache.as_mut() {
+        cache.current.insert(key, output);
+    }
+    Ok(())
+}
+
 fn format_journal_item(state: &mut State, node: Node) -> Result<()> {
     match node.kind() {
         "comment" => state.print_node(node),
@@ -361,11 +856,11 @@ fn format_plain_xact(state: &mut State, node: Node) -> Result<()> {
         let value = child.utf8_text(state.code)?.trim();
         match child.kind() {
             "date" => {
-                state.print(value);
+                state.print(&format_date(value, state.settings.date_format));
             }
             "effective_date" => {
                 state.print("=");
-                state.print(value);
+                state.print(&format_date(value, state.settings.date_format));
             }
             "status" | "code" | "payee" => {
                 state.print(" ");
@@ -376,6 +871,7 @@ fn format_plain_xact(state: &mut State, node: Node) -> Result<()> {
     }
     state.println("");
     state.level += 1;
+    set_alignment_column(state, node)?;
     for child in node
         .named_children(&mut cursor)
         .filter(|c| !types_first_line.contains(&c.kind()))
@@ -384,7 +880,7 @@ fn format_plain_xact(state: &mut State, node: Node) -> Result<()> {
         match child.kind() {
             "note" => {
                 state.indent();
-                state.println(value);
+                state.println(&format_note(state, value));
             }
             "posting" => {
                 state.indent();
@@ -416,6 +912,7 @@ fn format_periodic_xact(state: &mut State, node: Node) -> Result<()> {
     }
     state.println("");
     state.level += 1;
+    set_alignment_column(state, node)?;
     let types_first_line = ["note", "interval"];
     for child in node
         .named_children(&mut cursor)
@@ -425,7 +922,7 @@ fn format_periodic_xact(state: &mut State, node: Node) -> Result<()> {
         match child.kind() {
             "note" => {
                 state.indent();
-                state.println(value);
+                state.println(&format_note(state, value));
             }
             "posting" => {
                 state.indent();
@@ -457,6 +954,7 @@ fn format_automated_xact(state: &mut State, node: Node) -> Result<()> {
     }
     state.println("");
     state.level += 1;
+    set_alignment_column(state, node)?;
     let types_first_line = ["note", "query"];
     for child in node
         .named_children(&mut cursor)
@@ -466,7 +964,7 @@ fn format_automated_xact(state: &mut State, node: Node) -> Result<()> {
         match child.kind() {
             "note" => {
                 state.indent();
-                state.println(value);
+                state.println(&format_note(state, value));
             }
             "posting" => {
                 state.indent();
@@ -479,6 +977,44 @@ fn format_automated_xact(state: &mut State, node: Node) -> Result<()> {
     Ok(())
 }
 
+/// Sets the column at which the amounts of the postings of `node` are aligned. Must be called with
+/// the indentation level of the postings.
+fn set_alignment_column(state: &mut State, node: Node) -> Result<()> {
+    state.alignment_column = match state.settings.amount_alignment {
+        AmountAlignment::Column => state.settings.alignment_column,
+        AmountAlignment::Transaction => {
+            let mut column = 0;
+            let mut cursor = node.walk();
+            for posting in node
+                .named_children(&mut cursor)
+                .filter(|c| c.kind() == "posting")
+            {
+                column = max(column, amount_column(state, posting)?);
+            }
+            column
+        }
+    };
+    Ok(())
+}
+
+/// The smallest column at which the amount of `posting` can be aligned, leaving two spaces after
+/// its account.
+fn amount_column(state: &State, posting: Node) -> Result<usize> {
+    let mut column = state.level * state.settings.indent_width + state.extra_indentation;
+    let mut cursor = posting.walk();
+    for child in posting.named_children(&mut cursor) {
+        match child.kind() {
+            "status" | "account" => column += child.utf8_text(state.code)?.graphemes(true).count(),
+            "amount" => {
+                let (prefix, quantity, _) = amount_parts(state, child)?;
+                column += 2 + prefix.graphemes(true).count() + quantity.len() + 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(column)
+}
+
 fn format_posting(state: &mut State, node: Node) -> Result<()> {
     let mut cursor = node.walk();
     if let Some(status) = node
@@ -495,22 +1031,34 @@ fn format_posting(state: &mut State, node: Node) -> Result<()> {
         let text = account.utf8_text(state.code)?;
         state.print(text);
     }
-    let mut spacing = " ".repeat(max(0, 60 - state.col));
+    let column = state.alignment_column;
+    let mut spacing = " ".repeat(column.saturating_sub(state.col));
     if let Some(amount) = node
         .named_children(&mut cursor)
         .find(|c| c.kind() == "amount")
     {
-        let mut cursor = amount.walk();
-        let number_size = amount
-            .named_children(&mut cursor)
+        let mut amount_cursor = amount.walk();
+        amount
+            .named_children(&mut amount_cursor)
             .find(|c| c.kind() == "quantity" || c.kind() == "negative_quantity")
-            .err_at_loc(&amount)?
-            .utf8_text(state.code)?
-            .trim()
-            .len();
-        let quantity_spacing = " ".repeat(max(0, 60 - state.col - number_size - 1));
-        state.print(&quantity_spacing);
-        format_amount(state, amount)?;
+            .err_at_loc(&amount)?;
+        let (prefix, quantity, suffix) = amount_parts(state, amount)?;
+        let number_size = prefix.graphemes(true).count() + quantity.len();
+        let quantity_spacing = column.saturating_sub(state.col + number_size + 1);
+        state.print(&" ".repeat(quantity_spacing));
+        state.print(&prefix);
+        state.print(quantity);
+        state.print(&suffix);
+        let annotation = &state.code[lot_annotation(node, amount)];
+        let annotation = std::str::from_utf8(annotation)?
+            .lines()
+            .next()
+            .unwrap_or("")
+            .trim();
+        if !annotation.is_empty() {
+            state.print(" ");
+            state.print(annotation);
+        }
         spacing = " ".into();
     }
     if let Some(price) = node
@@ -534,34 +1082,100 @@ fn format_posting(state: &mut State, node: Node) -> Result<()> {
         .find(|c| c.kind() == "note")
     {
         state.print(&spacing);
-        state.print(note.utf8_text(state.code)?.trim());
+        state.print(&format_note(state, note.utf8_text(state.code)?.trim()));
     }
     state.println("");
     Ok(())
 }
 
 fn format_amount(state: &mut State, node: Node) -> Result<()> {
+    let (prefix, quantity, suffix) = amount_parts(state, node)?;
+    state.print(&prefix);
+    state.print(quantity);
+    state.print(&suffix);
+    Ok(())
+}
+
+/// Splits an amount into what is printed before its quantity, the quantity, and what is printed
+/// after it, placing the commodity as configured.
+fn amount_parts<'a>(state: &State<'a>, node: Node) -> Result<(String, &'a str, String)> {
     let mut cursor = node.walk();
-    if let Some(negative_quantity) = node
+    let quantity = node
         .named_children(&mut cursor)
-        .find(|c| c.kind() == "negative_quantity")
-    {
-        state.print(negative_quantity.utf8_text(state.code)?.trim());
-    }
-    if let Some(quantity) = node
-        .named_children(&mut cursor)
-        .find(|c| c.kind() == "quantity")
-    {
-        state.print(quantity.utf8_text(state.code)?.trim());
-    }
-    if let Some(commodity) = node
+        .find(|c| c.kind() == "quantity" || c.kind() == "negative_quantity");
+    let quantity_text = match quantity {
+        Some(quantity) => quantity.utf8_text(state.code)?.trim(),
+        None => "",
+    };
+    let Some(commodity) = node
         .named_children(&mut cursor)
         .find(|c| c.kind() == "commodity")
-    {
-        state.print(" ");
-        state.print(commodity.utf8_text(state.code)?.trim());
+    else {
+        return Ok((String::new(), quantity_text, String::new()));
+    };
+    let commodity_text = commodity.utf8_text(state.code)?.trim();
+    let written_before = quantity.is_some_and(|q| commodity.start_byte() < q.start_byte());
+    let (before, spaced) = match state.settings.commodity_position {
+        CommodityPosition::After => (false, true),
+        CommodityPosition::Before => (true, !is_symbol(commodity_text)),
+        CommodityPosition::Preserve if written_before => (
+            true,
+            quantity.is_some_and(|q| commodity.end_byte() < q.start_byte()),
+        ),
+        CommodityPosition::Preserve => (
+            false,
+            quantity.is_some_and(|q| q.end_byte() < commodity.start_byte()),
+        ),
+    };
+    let spacing = if spaced { " " } else { "" };
+    if before {
+        Ok((
+            format!("{commodity_text}{spacing}"),
+            quantity_text,
+            String::new(),
+        ))
+    } else {
+        Ok((
+            String::new(),
+            quantity_text,
+            format!("{spacing}{commodity_text}"),
+        ))
     }
-    Ok(())
+}
+
+/// Whether `commodity` is a single symbol, like `$` or `€`, that is written next to the quantity.
+fn is_symbol(commodity: &str) -> bool {
+    let mut chars = commodity.chars();
+    matches!((chars.next(), chars.next()), (Some(c), None) if !c.is_alphanumeric())
+}
+
+/// Rewrites `date` with the separator of `format`, padding months and days to two digits. Dates
+/// that do not look like `[year/]month/day` are kept as written.
+pub(crate) fn format_date(date: &str, format: DateFormat) -> String {
+    let separator = match format {
+        DateFormat::Preserve => return date.to_string(),
+        DateFormat::Iso => "-",
+        DateFormat::Slash => "/",
+        DateFormat::Dot => ".",
+    };
+    let parts: Vec<&str> = date.split(['-', '/', '.']).collect();
+    let is_number = |part: &&str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    if !(2..=3).contains(&parts.len()) || !parts.iter().all(is_number) {
+        return date.to_string();
+    }
+    let year = parts.len() - 2;
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if i < year {
+                part.to_string()
+            } else {
+                format!("{part:0>2}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(separator)
 }
 
 fn format_price(state: &mut State, node: Node) -> Result<()> {
@@ -575,12 +1189,341 @@ fn format_price(state: &mut State, node: Node) -> Result<()> {
     format_amount(state, amount)
 }
 
+/// The bytes of `posting` between `amount` and the price, balance assertion or note after it, where
+/// lot annotations like `{10 EUR}`, `{{20 EUR}}` or `[2024/01/01]` are written. The grammar reads
+/// them as no node, or as nodes the formatter does not know, so they are copied from the source.
+pub(crate) fn lot_annotation(posting: Node, amount: Node) -> Range<usize> {
+    let mut cursor = posting.walk();
+    let end = posting
+        .named_children(&mut cursor)
+        .find(|child| {
+            child.start_byte() >= amount.end_byte()
+                && matches!(child.kind(), "price" | "balance_assertion" | "note")
+        })
+        .map_or(posting.end_byte(), |next| next.start_byte());
+    amount.end_byte()..end.max(amount.end_byte())
+}
+
 fn format_balance_assertion(state: &mut State, node: Node) -> Result<()> {
-    state.print("= ");
     let mut cursor = node.walk();
     let amount = node
         .named_children(&mut cursor)
         .find(|c| c.kind() == "amount")
         .err_at_loc(&node)?;
+    // hledger's `==`, `=*` and `==*` assert something else than `=`, so the operator is kept.
+    let operator = std::str::from_utf8(&state.code[node.start_byte()..amount.start_byte()])?.trim();
+    state.print(if operator.is_empty() { "=" } else { operator });
+    state.print(" ");
     format_amount(state, amount)
 }
+
+/// Rewrites the dates of hledger's `date:` and `date2:` posting tags in `note` as configured.
+fn format_note<'a>(state: &State, note: &'a str) -> Cow<'a, str> {
+    if state.settings.dialect != Dialect::Hledger
+        || state.settings.date_format == DateFormat::Preserve
+    {
+        return Cow::Borrowed(note);
+    }
+    let mut formatted = String::with_capacity(note.len());
+    let mut rest = note;
+    while let Some(index) = rest.find("date") {
+        let (before, tag) = rest.split_at(index);
+        formatted += before;
+        let at_word_start = before
+            .chars()
+            .next_back()
+            .is_none_or(|c| c.is_whitespace() || c == ',' || c == ';');
+        let name_len = if tag.starts_with("date:") {
+            "date:".len()
+        } else if tag.starts_with("date2:") {
+            "date2:".len()
+        } else {
+            0
+        };
+        if !at_word_start || name_len == 0 {
+            formatted += "date";
+            rest = &tag["date".len()..];
+            continue;
+        }
+        let value = &tag[name_len..];
+        let value_len = value
+            .find(|c: char| c.is_whitespace() || c == ',' || c == ';')
+            .unwrap_or(value.len());
+        formatted += &tag[..name_len];
+        formatted += &format_date(&value[..value_len], state.settings.date_format);
+        rest = &value[value_len..];
+    }
+    formatted += rest;
+    Cow::Owned(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            sort: false,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn keeps_balance_assertion_operators() {
+        let code =
+            "2024/01/01 Check\n  Assets:Cash  0 EUR == 10 EUR\n  Assets:Bank  0 EUR =* 5 EUR\n";
+        for dialect in [Dialect::Ledger, Dialect::Hledger] {
+            let settings = Settings {
+                dialect,
+                ..settings()
+            };
+            let formatted = beautify(code, &settings).unwrap();
+            assert!(formatted.contains("== 10 EUR"), "{formatted}");
+            assert!(formatted.contains("=* 5 EUR"), "{formatted}");
+        }
+    }
+
+    #[test]
+    fn keeps_amounts_with_a_comma_decimal_mark() {
+        let code = "decimal-mark ,\n\n2024/01/01 Shop\n    Expenses:Food    1.234,56 EUR\n    \
+                    Assets:Cash    -1.234,56 EUR = 10,5 EUR\n";
+        for dialect in [Dialect::Ledger, Dialect::Hledger] {
+            let settings = Settings {
+                dialect,
+                amount_alignment: AmountAlignment::Transaction,
+                ..settings()
+            };
+            let formatted = beautify(code, &settings).unwrap();
+            assert!(formatted.contains("decimal-mark ,\n"), "{formatted}");
+            assert!(
+                formatted.contains("  Expenses:Food  1.234,56 EUR\n"),
+                "{formatted}"
+            );
+            assert!(
+                formatted.contains("  Assets:Cash   -1.234,56 EUR = 10,5 EUR\n"),
+                "{formatted}"
+            );
+        }
+    }
+
+    #[test]
+    fn maps_the_cursor_through_converted_line_endings() {
+        let code = "; ledger-beautifier: off\r\n2024/01/01 Shop\r\n  Assets:Cash  10 EUR\r\n  \
+                    Income\r\n; ledger-beautifier: on\r\n";
+        let settings = Settings {
+            line_ending: LineEnding::Lf,
+            ..settings()
+        };
+        let cursor = code.find("Income").unwrap() + 2;
+        let (formatted, mapped) = beautify_with_cursor(code, &settings, cursor).unwrap();
+        assert!(!formatted.contains('\r'));
+        assert_eq!(mapped, formatted.find("Income").unwrap() + 2);
+    }
+
+    #[test]
+    fn tracks_spans_around_converted_line_breaks() {
+        // Stray carriage returns are dropped, and line breaks grow to CRLF.
+        let code = "a\r\rb\r\nc\nd";
+        let settings = settings();
+        let mut state = State {
+            settings: &settings,
+            code: code.as_bytes(),
+            col: 0,
+            row: 0,
+            level: 0,
+            extra_indentation: 0,
+            formatted: String::new(),
+            consecutive_ln_count: 0,
+            newline: "\r\n",
+            alignment_column: settings.alignment_column,
+            spans: Some(vec![]),
+            cache: None,
+        };
+        state.print_verbatim(code);
+        let spans = state.spans.take().unwrap();
+        assert_eq!(state.formatted, "ab\r\nc\r\nd");
+        for c in ['b', 'c', 'd'] {
+            let mapped = map_cursor(code, &spans, code.find(c).unwrap());
+            assert_eq!(mapped, state.formatted.find(c).unwrap());
+        }
+    }
+
+    #[test]
+    fn honours_control_comments() {
+        let unformatted = "2024/01/02 Shop\n    Expenses:Food    10 EUR\n    Assets:Cash\n";
+        let formatted = "2024/01/02 Shop\n  Expenses:Food  10 EUR\n  Assets:Cash\n";
+        let off = "; ledger-beautifier: off\n";
+        let on = "; ledger-beautifier: on\n";
+        let skip = "; ledger-beautifier: skip\n";
+        let settings = Settings {
+            amount_alignment: AmountAlignment::Transaction,
+            ..settings()
+        };
+        // Each input, the text it must keep as is, and how many transactions it formats.
+        let cases = [
+            (unformatted.to_string(), String::new(), 1),
+            (
+                format!("{off}{unformatted}{on}\n{unformatted}"),
+                format!("{off}{unformatted}{on}"),
+                1,
+            ),
+            (
+                format!("{skip}{unformatted}\n{unformatted}"),
+                format!("{skip}{unformatted}"),
+                1,
+            ),
+            // An `off` right after a `skip` still protects everything up to its `on`.
+            (
+                format!("{skip}{off}{unformatted}\n{unformatted}{on}\n{unformatted}"),
+                format!("{skip}{off}{unformatted}\n{unformatted}{on}"),
+                1,
+            ),
+            (
+                format!("{skip}{skip}{unformatted}\n{unformatted}"),
+                format!("{skip}{skip}{unformatted}"),
+                1,
+            ),
+            // Without an `on`, the rest of the file is protected.
+            (
+                format!("{unformatted}\n{off}{unformatted}"),
+                format!("{off}{unformatted}"),
+                1,
+            ),
+            // An `on` without an `off` changes nothing.
+            (format!("{on}\n{unformatted}"), String::new(), 1),
+        ];
+        for (code, protected, count) in cases {
+            let result = beautify(&code, &settings).unwrap();
+            assert!(result.contains(&protected), "{result}");
+            assert_eq!(result.matches(formatted).count(), count, "{result}");
+        }
+    }
+
+    #[test]
+    fn detects_line_endings() {
+        assert_eq!(detect_line_ending(""), "\n");
+        assert_eq!(detect_line_ending("a\nb\n"), "\n");
+        assert_eq!(detect_line_ending("a\r\nb\r\n"), "\r\n");
+        assert_eq!(detect_line_ending("a\r\nb\nc\r\n"), "\r\n");
+        assert_eq!(detect_line_ending("a\r\nb\n"), "\n");
+        assert_eq!(LineEnding::Auto.resolve("a\r\n"), "\r\n");
+        assert_eq!(LineEnding::Lf.resolve("a\r\n"), "\n");
+        assert_eq!(LineEnding::Crlf.resolve("a\n"), "\r\n");
+    }
+
+    #[test]
+    fn normalizes_newlines() {
+        assert!(matches!(normalize_newlines("a\nb", "\n"), Cow::Borrowed(_)));
+        assert!(matches!(normalize_newlines("ab", "\r\n"), Cow::Borrowed(_)));
+        assert_eq!(normalize_newlines("a\r\nb\rc\n", "\n"), "a\nbc\n");
+        assert_eq!(normalize_newlines("a\r\nb\nc", "\r\n"), "a\r\nb\r\nc");
+    }
+
+    #[test]
+    fn keeps_or_converts_line_endings() {
+        let code = "2024/01/01 Shop\r\n  Expenses:Food  10 EUR\r\n  Assets:Cash\r\n";
+        let formatted = beautify(code, &settings()).unwrap();
+        assert_eq!(
+            formatted.matches("\r\n").count(),
+            formatted.matches('\n').count()
+        );
+        let settings = Settings {
+            line_ending: LineEnding::Lf,
+            ..settings()
+        };
+        assert!(!beautify(code, &settings).unwrap().contains('\r'));
+    }
+
+    const ITEMS: &str = "2024/01/02 Shop\n    Expenses:Food    10 EUR\n    Assets:Cash\n\n\
+                         2024/01/01 Salary\n    Assets:Bank    100 EUR\n    Income:Salary\n";
+    const SHOP: &str = "2024/01/02 Shop\n  Expenses:Food  10 EUR\n  Assets:Cash\n";
+    const SALARY: &str = "2024/01/01 Salary\n  Assets:Bank  100 EUR\n  Income:Salary\n";
+
+    fn aligned() -> Settings {
+        Settings {
+            amount_alignment: AmountAlignment::Transaction,
+            ..settings()
+        }
+    }
+
+    fn format_range_of(code: &str, range: Range<usize>) -> String {
+        crate::apply_edits(code, &format_range(code, range, &aligned()).unwrap())
+    }
+
+    #[test]
+    fn formats_ranges() {
+        let (shop, salary) = ITEMS.split_at(ITEMS.find("\n\n").unwrap() + 1);
+        // An empty range formats the item around it, without a blank line before it.
+        let offset = ITEMS.find("Expenses").unwrap();
+        assert_eq!(
+            format_range_of(ITEMS, offset..offset),
+            format!("{SHOP}{salary}")
+        );
+        // On the blank line between the items, there is nothing to format.
+        let offset = shop.len();
+        assert!(format_range(ITEMS, offset..offset, &aligned())
+            .unwrap()
+            .is_empty());
+        // Every item the range touches is formatted, and they are not sorted.
+        let offset = ITEMS.find("Salary").unwrap();
+        assert_eq!(
+            format_range_of(ITEMS, 20..offset),
+            format!("{SHOP}\n{SALARY}")
+        );
+        assert_eq!(
+            format_range_of(ITEMS, 0..ITEMS.len()),
+            format!("{SHOP}\n{SALARY}")
+        );
+    }
+
+    #[test]
+    fn leaves_formatted_and_protected_ranges_alone() {
+        let formatted = format!("{SHOP}\n{SALARY}");
+        assert!(format_range(&formatted, 0..formatted.len(), &aligned())
+            .unwrap()
+            .is_empty());
+
+        let code = format!(
+            "; ledger-beautifier: off\n{ITEMS}; ledger-beautifier: on\n\n{}",
+            &ITEMS[..ITEMS.find("\n\n").unwrap() + 1]
+        );
+        let offset = code.find("Salary").unwrap();
+        assert!(format_range(&code, offset..offset + 1, &aligned())
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            format_range_of(&code, 0..code.len()),
+            format!("; ledger-beautifier: off\n{ITEMS}; ledger-beautifier: on\n\n{SHOP}")
+        );
+    }
+
+    #[test]
+    fn formats_the_item_at_an_offset() {
+        let (shop, salary) = ITEMS.split_at(ITEMS.find("\n\n").unwrap() + 1);
+        let format_at = |code: &str, offset: usize| format_item_at(code, offset, &aligned());
+
+        let offset = ITEMS.find("Food").unwrap();
+        let edit = format_at(ITEMS, offset).unwrap().unwrap();
+        assert_eq!(edit.range.start, 0);
+        assert_eq!(edit.new_text.trim_end(), SHOP.trim_end());
+        assert_eq!(
+            crate::apply_edits(ITEMS, &[edit]),
+            format!("{SHOP}{salary}")
+        );
+
+        // Right after the line break of the last posting, as when it was just typed.
+        let edit = format_at(ITEMS, shop.len()).unwrap().unwrap();
+        assert_eq!(
+            crate::apply_edits(ITEMS, &[edit]),
+            format!("{SHOP}{salary}")
+        );
+
+        // Between the items, one line further.
+        let code = format!("{shop}\n{salary}");
+        assert_eq!(format_at(&code, shop.len() + 1).unwrap(), None);
+
+        let code = "; é\n2024/01/01 Shop\n  Assets:Cash  1 EUR\n  Income\n";
+        assert!(format_at(code, 3).is_err());
+        assert!(format_at(code, code.len() + 1).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use super::import::ImportRules;
+use super::journal::{Item, Journal, Transaction};
+
+/// Guesses the other side of imported transactions from the words of their payee and notes, with
+/// a naive Bayes classifier trained on the transactions of a journal.
+#[derive(Debug, Clone, Default)]
+pub struct Classifier {
+    accounts: HashMap<String, AccountWords>,
+    vocabulary: HashSet<String>,
+    examples: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct AccountWords {
+    /// How many transactions went to the account.
+    examples: usize,
+    /// How many times each word appeared in them.
+    words: HashMap<String, usize>,
+    total: usize,
+}
+
+impl Classifier {
+    /// Learns from the transactions of `journal` that have a posting to one of the statement
+    /// accounts of `rules` and exactly one posting to another account, which is what they are
+    /// labeled with. Transactions left on `default-account` by earlier imports are not learned from.
+    pub fn train(journal: &Journal, rules: &ImportRules) -> Classifier {
+        let statement_accounts = rules.statement_accounts();
+        let mut classifier = Classifier::default();
+        for item in &journal.items {
+            let Item::Transaction(transaction) = item else {
+                continue;
+            };
+            let (statement, other): (Vec<_>, Vec<_>) = transaction
+                .postings
+                .iter()
+                .partition(|posting| statement_accounts.contains(posting.account.as_str()));
+            if statement.is_empty() || other.len() != 1 || other[0].account == rules.default_account
+            {
+                continue;
+            }
+            let account = classifier
+                .accounts
+                .entry(other[0].account.clone())
+                .or_default();
+            account.examples += 1;
+            for word in words(transaction) {
+                *account.words.entry(word.clone()).or_default() += 1;
+                account.total += 1;
+                classifier.vocabulary.insert(word);
+            }
+            classifier.examples += 1;
+        }
+        classifier
+    }
+
+    /// The most likely account for `transaction` and its probability, or nothing when none of its
+    /// words were seen in training.
+    pub fn predict(&self, transaction: &Transaction) -> Option<(String, f64)> {
+        let words: Vec<String> = words(transaction)
+            .into_iter()
+            .filter(|word| self.vocabulary.contains(word))
+            .collect();
+        if words.is_empty() {
+            return None;
+        }
+        let vocabulary = self.vocabulary.len() as f64;
+        let scores: Vec<(&String, f64)> = self
+            .accounts
+            .iter()
+            .map(|(name, account)| {
+                let prior = (account.examples as f64 / self.examples as f64).ln();
+                let likelihood: f64 = words
+                    .iter()
+                    .map(|word| {
+                        let count = account.words.get(word).copied().unwrap_or_default();
+                        ((count as f64 + 1.0) / (account.total as f64 + vocabulary)).ln()
+                    })
+                    .sum();
+                (name, prior + likelihood)
+            })
+            .collect();
+        let best = scores.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1))?;
+        // The probabilities are the exponentials of the scores, normalized to add up to 1.
+        let total: f64 = scores.iter().map(|(_, score)| (score - best.1).exp()).sum();
+        Some((best.0.clone(), 1.0 / total))
+    }
+}
+
+/// Replaces `default-account` in the imported transactions with the account `classifier` guesses,
+/// tagging the ones whose guess is not likely enough for review.
+pub fn categorize(journal: &mut Journal, classifier: &Classifier, rules: &ImportRules) {
+    for item in &mut journal.items {
+        let Item::Transaction(transaction) = item else {
+            continue;
+        };
+        let Some((account, probability)) = classifier.predict(transaction) else {
+            continue;
+        };
+        let Some(posting) = transaction
+            .postings
+            .iter_mut()
+            .find(|posting| posting.account == rules.default_account)
+        else {
+            continue;
+        };
+        posting.account = account.clone();
+        if probability < rules.classify_confidence {
+            transaction.notes.push(format!(
+                "; :{}: {account} guessed with {:.0}% confidence",
+                rules.review_tag,
+                probability * 100.0
+            ));
+        }
+    }
+}
+
+/// The lowercase words of the payee and notes of `transaction`, leaving out numbers and the
+/// values of metadata tags, which are different for every transaction.
+fn words(transaction: &Transaction) -> Vec<String> {
+    let notes = transaction
+        .notes
+        .iter()
+        .map(|note| note.trim_start_matches(';').trim())
+        .filter(|note| !note.contains(": ") && !note.starts_with(':'));
+    transaction
+        .payee
+        .iter()
+        .map(String::as_str)
+        .chain(notes)
+        .flat_map(|text| text.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| word.chars().count() > 1 && !word.chars().all(|c| c.is_numeric()))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::Posting;
+
+    fn transaction(payee: &str, accounts: &[&str]) -> Transaction {
+        Transaction {
+            payee: Some(payee.to_string()),
+            postings: accounts
+                .iter()
+                .map(|account| Posting {
+                    account: account.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn history() -> Journal {
+        let transactions = [
+            transaction("Lidl 123", &["Assets:Checking", "Expenses:Groceries"]),
+            transaction("Lidl Berlin", &["Assets:Checking", "Expenses:Groceries"]),
+            transaction("Aldi", &["Assets:Checking", "Expenses:Groceries"]),
+            transaction("Shell station", &["Assets:Checking", "Expenses:Fuel"]),
+            transaction("Shell", &["Assets:Checking", "Expenses:Fuel"]),
+            // Not learned from: no statement account, more than one other posting, or the default
+            // account.
+            transaction("Shell", &["Assets:Cash", "Expenses:Groceries"]),
+            transaction(
+                "Shell",
+                &["Assets:Checking", "Expenses:Groceries", "Expenses:Fuel"],
+            ),
+            transaction("Shell", &["Assets:Checking", "Expenses:Unknown"]),
+            transaction("Shell", &["Assets:Checking", "Expenses:Unknown"]),
+        ];
+        Journal {
+            items: transactions.into_iter().map(Item::Transaction).collect(),
+        }
+    }
+
+    fn rules(toml: &str) -> ImportRules {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn classifier() -> Classifier {
+        let rules = rules(
+            r#"
+            account = "Assets:Checking"
+            default-account = "Expenses:Unknown"
+            "#,
+        );
+        Classifier::train(&history(), &rules)
+    }
+
+    #[test]
+    fn splits_words() {
+        let mut transaction = transaction("LIDL 4711 Berlin-Mitte", &[]);
+        transaction.notes = vec!["; weekly shop".to_string(), "; FITID: 99".to_string()];
+        assert_eq!(
+            words(&transaction),
+            ["lidl", "berlin", "mitte", "weekly", "shop"]
+        );
+    }
+
+    #[test]
+    fn predicts_accounts() {
+        let classifier = classifier();
+        assert_eq!(classifier.examples, 5);
+        assert!(!classifier.accounts.contains_key("Expenses:Unknown"));
+        let (account, probability) = classifier.predict(&transaction("LIDL", &[])).unwrap();
+        assert_eq!(account, "Expenses:Groceries");
+        assert!(probability > 0.5 && probability <= 1.0);
+        let (account, _) = classifier.predict(&transaction("Shell", &[])).unwrap();
+        assert_eq!(account, "Expenses:Fuel");
+        assert_eq!(classifier.predict(&transaction("Unknown", &[])), None);
+    }
+
+    #[test]
+    fn categorizes_default_accounts() {
+        let rules = rules(
+            r#"
+            default-account = "Expenses:Unknown"
+            classify-confidence = 0.99
+            "#,
+        );
+        let mut journal = Journal {
+            items: vec![
+                Item::Transaction(transaction(
+                    "Lidl",
+                    &["Assets:Checking", "Expenses:Unknown"],
+                )),
+                Item::Transaction(transaction("Lidl", &["Assets:Checking", "Expenses:Rent"])),
+                Item::Transaction(transaction(
+                    "Rent",
+                    &["Assets:Checking", "Expenses:Unknown"],
+                )),
+            ],
+        };
+        categorize(&mut journal, &classifier(), &rules);
+        let accounts: Vec<&str> = journal
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::Transaction(transaction) => transaction.postings[1].account.as_str(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            accounts,
+            ["Expenses:Groceries", "Expenses:Rent", "Expenses:Unknown"]
+        );
+        let Item::Transaction(guessed) = &journal.items[0] else {
+            unreachable!()
+        };
+        assert!(guessed.notes[0].starts_with("; :review: Expenses:Groceries guessed with"));
+    }
+}
@@ -0,0 +1,421 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::path::{Path, PathBuf};
+
+use super::args::{
+    AmountAlignment, Arguments, CommodityPosition, DateFormat, Dialect, IndentStyle, LineEnding,
+    Style, ValueEnum,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Name of the configuration file searched for next to each input and in its parents.
+pub const CONFIG_FILE_NAME: &str = ".ledger-beautifier.toml";
+
+/// The contents of a configuration file. Unset fields are taken from configuration files further
+/// up the directory tree, or from the defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Stops the search for configuration files in parent directories.
+    #[serde(default)]
+    pub root: bool,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub style: Option<Style>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub dialect: Option<Dialect>,
+    pub alignment_column: Option<usize>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub amount_alignment: Option<AmountAlignment>,
+    pub indent_width: Option<usize>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub indent_style: Option<IndentStyle>,
+    pub final_newline: Option<bool>,
+    pub sort: Option<bool>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub commodity_position: Option<CommodityPosition>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub date_format: Option<DateFormat>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub line_ending: Option<LineEnding>,
+    /// Glob patterns, relative to the configuration file, of files that are left untouched.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// The settings used to format a file, after combining the defaults, configuration files and
+/// command line arguments.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Settings {
+    #[serde(serialize_with = "value_enum_name")]
+    pub style: Style,
+    #[serde(serialize_with = "value_enum_name")]
+    pub dialect: Dialect,
+    pub alignment_column: usize,
+    #[serde(serialize_with = "value_enum_name")]
+    pub amount_alignment: AmountAlignment,
+    pub indent_width: usize,
+    #[serde(serialize_with = "value_enum_name")]
+    pub indent_style: IndentStyle,
+    pub final_newline: bool,
+    pub sort: bool,
+    #[serde(serialize_with = "value_enum_name")]
+    pub commodity_position: CommodityPosition,
+    #[serde(serialize_with = "value_enum_name")]
+    pub date_format: DateFormat,
+    #[serde(serialize_with = "value_enum_name")]
+    pub line_ending: LineEnding,
+    /// Whether the file matches an `exclude` pattern and must not be formatted. Not an option of
+    /// configuration files, so it is not serialized.
+    #[serde(skip)]
+    pub excluded: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            style: Style::Default,
+            dialect: Dialect::Ledger,
+            alignment_column: 60,
+            amount_alignment: AmountAlignment::Column,
+            indent_width: 2,
+            indent_style: IndentStyle::Space,
+            final_newline: true,
+            sort: true,
+            commodity_position: CommodityPosition::After,
+            date_format: DateFormat::Preserve,
+            line_ending: LineEnding::Auto,
+            excluded: false,
+        }
+    }
+}
+
+impl Config {
+    /// Reads and parses the configuration file at `path`.
+    pub fn load(path: &Path) -> Result<Config> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("Could not parse {}", path.display()))
+    }
+}
+
+impl Settings {
+    /// Resolves the settings for the file at `path`, or for the working directory when there is no
+    /// path, by reading the EditorConfig properties, the style preset, the configuration files that
+    /// apply to it and then `arguments`, each one taking precedence over the previous.
+    pub fn resolve(arguments: &Arguments, path: Option<&Path>) -> Result<Settings> {
+        let mut settings = Settings::default();
+        if let Some(path) = path {
+            settings.apply(&editorconfig(path)?);
+        }
+        let configs = find_configs(path)?;
+        let style = arguments
+            .style
+            .or_else(|| configs.iter().find_map(|(_, config)| config.style));
+        if let Some(style) = style {
+            settings.apply(&style.preset());
+        }
+        for (config_path, config) in configs.iter().rev() {
+            settings.apply(config);
+            if let (Some(path), Some(dir)) = (path, config_path.parent()) {
+                settings.excluded |= is_excluded(path, dir, &config.exclude);
+            }
+        }
+        settings.apply_arguments(arguments);
+        Ok(settings)
+    }
+
+    /// Overwrites the settings that are set in `config`.
+    pub fn apply(&mut self, config: &Config) {
+        if let Some(style) = config.style {
+            self.style = style;
+        }
+        if let Some(dialect) = config.dialect {
+            self.dialect = dialect;
+        }
+        if let Some(alignment_column) = config.alignment_column {
+            self.alignment_column = alignment_column;
+        }
+        if let Some(amount_alignment) = config.amount_alignment {
+            self.amount_alignment = amount_alignment;
+        }
+        if let Some(indent_width) = config.indent_width {
+            self.indent_width = indent_width;
+        }
+        if let Some(indent_style) = config.indent_style {
+            self.indent_style = indent_style;
+        }
+        if let Some(final_newline) = config.final_newline {
+            self.final_newline = final_newline;
+        }
+        if let Some(sort) = config.sort {
+            self.sort = sort;
+        }
+        if let Some(commodity_position) = config.commodity_position {
+            self.commodity_position = commodity_position;
+        }
+        if let Some(date_format) = config.date_format {
+            self.date_format = date_format;
+        }
+        if let Some(line_ending) = config.line_ending {
+            self.line_ending = line_ending;
+        }
+    }
+
+    fn apply_arguments(&mut self, arguments: &Arguments) {
+        self.apply(&Config {
+            style: arguments.style,
+            dialect: arguments.dialect,
+            alignment_column: arguments.alignment_column,
+            amount_alignment: arguments.amount_alignment,
+            indent_width: arguments.indent_width,
+            indent_style: arguments.indent_style,
+            final_newline: arguments.final_newline,
+            sort: arguments.sort,
+            commodity_position: arguments.commodity_position,
+            date_format: arguments.date_format,
+            line_ending: arguments.line_ending,
+            ..Default::default()
+        });
+    }
+
+    /// Renders the settings as a configuration file.
+    pub fn to_toml(&self) -> Result<String> {
+        let toml = toml::to_string(self).with_context(|| "Could not serialize settings")?;
+        if self.excluded {
+            return Ok(format!(
+                "# Excluded from formatting by an `exclude` pattern.\n{toml}"
+            ));
+        }
+        Ok(toml)
+    }
+}
+
+impl Style {
+    /// The options set by this preset.
+    pub fn preset(self) -> Config {
+        let (alignment_column, amount_alignment, indent_width, commodity_position) = match self {
+            Style::Default => (60, AmountAlignment::Column, 2, CommodityPosition::After),
+            Style::LedgerMode => (52, AmountAlignment::Column, 4, CommodityPosition::Preserve),
+            Style::HledgerPrint => (
+                60,
+                AmountAlignment::Transaction,
+                4,
+                CommodityPosition::Preserve,
+            ),
+        };
+        Config {
+            style: Some(self),
+            alignment_column: Some(alignment_column),
+            amount_alignment: Some(amount_alignment),
+            indent_width: Some(indent_width),
+            commodity_position: Some(commodity_position),
+            ..Default::default()
+        }
+    }
+}
+
+/// Finds the configuration files that apply to `path`, nearest first, up to the first one marked
+/// as `root`.
+pub fn find_configs(path: Option<&Path>) -> Result<Vec<(PathBuf, Config)>> {
+    let cwd = std::env::current_dir()?;
+    let start = match path {
+        Some(path) => cwd
+            .join(path)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or(cwd),
+        None => cwd,
+    };
+    let mut configs = vec![];
+    for dir in start.ancestors() {
+        let config_path = dir.join(CONFIG_FILE_NAME);
+        if !config_path.is_file() {
+            continue;
+        }
+        let config = Config::load(&config_path)?;
+        let root = config.root;
+        configs.push((config_path, config));
+        if root {
+            break;
+        }
+    }
+    Ok(configs)
+}
+
+/// Reads the EditorConfig properties that apply to `path` into a `Config`.
+pub fn editorconfig(path: &Path) -> Result<Config> {
+    use ec4rs::property::{EndOfLine, FinalNewline, IndentSize, TabWidth};
+
+    let mut properties = ec4rs::properties_of(path)
+        .with_context(|| format!("Could not read the EditorConfig of {}", path.display()))?;
+    properties.use_fallbacks();
+    let indent_style = match properties.get::<ec4rs::property::IndentStyle>() {
+        Ok(ec4rs::property::IndentStyle::Tabs) => Some(IndentStyle::Tab),
+        Ok(ec4rs::property::IndentStyle::Spaces) => Some(IndentStyle::Space),
+        Err(_) => None,
+    };
+    let indent_width = match properties.get::<IndentSize>() {
+        Ok(IndentSize::Value(size)) => Some(size),
+        Ok(IndentSize::UseTabWidth) => match properties.get::<TabWidth>() {
+            Ok(TabWidth::Value(width)) => Some(width),
+            Err(_) => None,
+        },
+        Err(_) => None,
+    };
+    let line_ending = match properties.get::<EndOfLine>() {
+        Ok(EndOfLine::Lf) => Some(LineEnding::Lf),
+        Ok(EndOfLine::CrLf) => Some(LineEnding::Crlf),
+        _ => None,
+    };
+    let final_newline = match properties.get::<FinalNewline>() {
+        Ok(FinalNewline::Value(value)) => Some(value),
+        Err(_) => None,
+    };
+    Ok(Config {
+        indent_width,
+        indent_style,
+        final_newline,
+        line_ending,
+        ..Default::default()
+    })
+}
+
+fn is_excluded(path: &Path, dir: &Path, patterns: &[String]) -> bool {
+    let Ok(cwd) = std::env::current_dir() else {
+        return false;
+    };
+    let path = cwd.join(path);
+    let Ok(relative) = path.strip_prefix(dir) else {
+        return false;
+    };
+    patterns.iter().any(|pattern| {
+        glob::Pattern::new(pattern)
+            .map(|pattern| pattern.matches_path(relative))
+            .unwrap_or(false)
+    })
+}
+
+fn value_enum<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: ValueEnum,
+{
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    T::from_str(&value, true)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn value_enum_name<S, T>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: ValueEnum,
+{
+    match value.to_possible_value() {
+        Some(value) => serializer.serialize_str(value.get_name()),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn printed_settings_load_as_a_configuration_file() {
+        let settings = Settings {
+            style: Style::LedgerMode,
+            dialect: Dialect::Hledger,
+            alignment_column: 48,
+            amount_alignment: AmountAlignment::Transaction,
+            indent_width: 3,
+            indent_style: IndentStyle::Tab,
+            final_newline: false,
+            sort: false,
+            commodity_position: CommodityPosition::Before,
+            date_format: DateFormat::Iso,
+            line_ending: LineEnding::Crlf,
+            excluded: true,
+        };
+        let config: Config = toml::from_str(&settings.to_toml().unwrap()).unwrap();
+        let mut loaded = Settings::default();
+        loaded.apply(&config);
+        assert_eq!(
+            loaded,
+            Settings {
+                excluded: false,
+                ..settings
+            }
+        );
+    }
+
+    #[test]
+    fn presets_are_overridden_by_options() {
+        let config: Config = toml::from_str("style = \"ledger-mode\"\nindent-width = 8\n").unwrap();
+        let mut settings = Settings::default();
+        settings.apply(&config.style.unwrap().preset());
+        settings.apply(&config);
+        assert_eq!(settings.alignment_column, 52);
+        assert_eq!(settings.indent_width, 8);
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        assert!(toml::from_str::<Config>("excluded = true\n").is_err());
+        assert!(toml::from_str::<Config>("dialect = \"beancount\"\n").is_err());
+    }
+
+    #[test]
+    fn reads_editorconfig_below_configuration_files_and_options() {
+        use clap::Parser;
+
+        let dir = std::env::temp_dir().join(format!("ledger-beautifier-{}", std::process::id()));
+        let (plain, configured) = (dir.join("plain"), dir.join("configured"));
+        for sub in [&plain, &configured] {
+            std::fs::create_dir_all(sub).unwrap();
+            std::fs::write(
+                sub.join(".editorconfig"),
+                "root = true\n\n[*.ledger]\nindent_style = tab\nindent_size = 4\n\
+                 end_of_line = crlf\ninsert_final_newline = false\n",
+            )
+            .unwrap();
+        }
+        // Keep configuration files of the temporary directory's parents out of the test.
+        std::fs::write(plain.join(".ledger-beautifier.toml"), "root = true\n").unwrap();
+        std::fs::write(
+            configured.join(".ledger-beautifier.toml"),
+            "root = true\nindent-style = \"space\"\nindent-width = 3\n",
+        )
+        .unwrap();
+
+        let options = Arguments::parse_from(["ledger-beautifier"]);
+        let settings = Settings::resolve(&options, Some(&plain.join("a.ledger"))).unwrap();
+        assert_eq!(settings.indent_style, IndentStyle::Tab);
+        assert_eq!(settings.indent_width, 4);
+        assert_eq!(settings.line_ending, LineEnding::Crlf);
+        assert!(!settings.final_newline);
+
+        let settings = Settings::resolve(&options, Some(&configured.join("a.ledger"))).unwrap();
+        assert_eq!(settings.indent_style, IndentStyle::Space);
+        assert_eq!(settings.indent_width, 3);
+        assert_eq!(settings.line_ending, LineEnding::Crlf);
+
+        let options = Arguments::parse_from(["ledger-beautifier", "--indent-width", "5"]);
+        let settings = Settings::resolve(&options, Some(&configured.join("a.ledger"))).unwrap();
+        assert_eq!(settings.indent_width, 5);
+
+        // Files the `[*.ledger]` section does not match keep the defaults.
+        let settings = Settings::resolve(&options, Some(&plain.join("a.txt"))).unwrap();
+        assert_eq!(settings.indent_style, IndentStyle::Space);
+        assert!(settings.final_newline);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt;
+
+use serde::Serialize;
+use tree_sitter::{Node, Point};
+
+/// A location in the source. Both fields are zero-based and `column` counts bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The input could not be processed.
+    Error,
+    /// The input was processed, but part of it was lost or changed.
+    Warning,
+}
+
+/// A problem found in a ledger file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub hint: Option<String>,
+    pub start: Position,
+    pub end: Position,
+    /// The line where the problem starts, without its line ending.
+    pub source_line: String,
+}
+
+/// Returned by `beautify` when the input contains syntax errors.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl From<Point> for Position {
+    fn from(point: Point) -> Self {
+        Position {
+            line: point.row,
+            column: point.column,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (at line {}:{})",
+            self.message,
+            self.start.line + 1,
+            self.start.column + 1
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.diagnostics.first() {
+            Some(diagnostic) => write!(
+                f,
+                "Parsed file contain errors (at line {}).",
+                diagnostic.start.line + 1
+            ),
+            None => write!(f, "Parsed file contain errors."),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Collects a diagnostic for every ERROR and MISSING node below `node`.
+pub fn collect_diagnostics(node: Node, code: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    collect(node, code, &mut diagnostics);
+    diagnostics
+}
+
+fn collect(node: Node, code: &str, diagnostics: &mut Vec<Diagnostic>) {
+    if node.is_error() {
+        diagnostics.push(error_diagnostic(node, code));
+        return;
+    }
+    if node.is_missing() {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            message: format!("missing `{}`", node.kind()),
+            hint: Some(format!("expected `{}` here", node.kind())),
+            start: node.start_position().into(),
+            end: node.end_position().into(),
+            source_line: source_line(code, node.start_position().row),
+        });
+        return;
+    }
+    if !node.has_error() {
+        return;
+    }
+    for child in node.children(&mut node.walk()) {
+        collect(child, code, diagnostics);
+    }
+}
+
+fn error_diagnostic(node: Node, code: &str) -> Diagnostic {
+    let text = node
+        .utf8_text(code.as_bytes())
+        .unwrap_or_default()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim();
+    let expected = expected_symbols(node);
+    let hint = if !expected.is_empty() {
+        Some(format!("expected {}", expected.join(", ")))
+    } else {
+        node.parent()
+            .map(|parent| format!("while reading `{}`", parent.kind()))
+    };
+    let message = if text.is_empty() {
+        "syntax error".to_string()
+    } else {
+        format!("unexpected `{text}`")
+    };
+    Diagnostic {
+        severity: Severity::Error,
+        message,
+        hint,
+        start: node.start_position().into(),
+        end: node.end_position().into(),
+        source_line: source_line(code, node.start_position().row),
+    }
+}
+
+/// Lists the named symbols the parser would have accepted where `node` starts.
+fn expected_symbols(node: Node) -> Vec<String> {
+    let Some(previous) = node.prev_sibling() else {
+        return vec![];
+    };
+    let language = node.language();
+    let Some(lookahead) = language.lookahead_iterator(previous.next_parse_state()) else {
+        return vec![];
+    };
+    let mut expected: Vec<String> = vec![];
+    for symbol in lookahead {
+        if !language.node_kind_is_named(symbol) || !language.node_kind_is_visible(symbol) {
+            continue;
+        }
+        if let Some(kind) = language.node_kind_for_id(symbol) {
+            let kind = format!("`{kind}`");
+            if !expected.contains(&kind) {
+                expected.push(kind);
+            }
+        }
+    }
+    expected.truncate(5);
+    expected
+}
+
+/// A warning about the whole line `line` of `code`, counting from 1.
+pub fn line_warning(code: &str, line: usize, message: String, hint: Option<String>) -> Diagnostic {
+    let row = line.saturating_sub(1);
+    let source_line = source_line(code, row);
+    Diagnostic {
+        severity: Severity::Warning,
+        message,
+        hint,
+        start: Position {
+            line: row,
+            column: 0,
+        },
+        end: Position {
+            line: row,
+            column: source_line.len(),
+        },
+        source_line,
+    }
+}
+
+fn source_line(code: &str, row: usize) -> String {
+    code.lines()
+        .nth(row)
+        .unwrap_or_default()
+        .trim_end_matches('\r')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beautifier::new_parser;
+
+    const MALFORMED: &str = "2024/01/01 Shop
+    Assets:Cash  10 EUR )
+    Income
+
+2024/01/02 Other
+    Assets:Cash  (10 EUR
+    Income
+";
+
+    #[test]
+    fn reports_each_syntax_error() {
+        let tree = new_parser().unwrap().parse(MALFORMED, None).unwrap();
+        let diagnostics = collect_diagnostics(tree.root_node(), MALFORMED);
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.start.line).collect();
+        assert_eq!(lines, [1, 5], "{diagnostics:#?}");
+        for diagnostic in &diagnostics {
+            let line = MALFORMED.lines().nth(diagnostic.start.line).unwrap();
+            assert_eq!(diagnostic.severity, Severity::Error);
+            assert_eq!(diagnostic.source_line, line);
+            assert!(diagnostic.start.column > 0 && diagnostic.start.column < line.len());
+            assert!(diagnostic.hint.is_some(), "{diagnostic:?}");
+        }
+        assert!(collect_diagnostics(
+            new_parser()
+                .unwrap()
+                .parse("2024/01/01 Shop\n  Assets:Cash  1 EUR\n  Income\n", None)
+                .unwrap()
+                .root_node(),
+            ""
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn lists_the_symbols_the_parser_expected() {
+        let tree = new_parser().unwrap().parse(MALFORMED, None).unwrap();
+        let diagnostics = collect_diagnostics(tree.root_node(), MALFORMED);
+        let hints: Vec<&str> = diagnostics
+            .iter()
+            .filter_map(|diagnostic| diagnostic.hint.as_deref())
+            .filter(|hint| hint.starts_with("expected `"))
+            .collect();
+        assert!(!hints.is_empty(), "{diagnostics:#?}");
+        for hint in hints {
+            assert!(hint.split(", ").count() <= 5, "{hint}");
+        }
+    }
+
+    #[test]
+    fn describes_positions_from_one() {
+        let warning = line_warning(
+            "a\r\nb c\r\n",
+            2,
+            "odd".to_string(),
+            Some("fix it".to_string()),
+        );
+        assert_eq!(warning.severity, Severity::Warning);
+        assert_eq!(warning.source_line, "b c");
+        assert_eq!(warning.start, Position { line: 1, column: 0 });
+        assert_eq!(warning.end, Position { line: 1, column: 3 });
+        assert_eq!(warning.to_string(), "odd (at line 2:1)");
+        let error = ParseError {
+            diagnostics: vec![warning],
+        };
+        assert_eq!(error.to_string(), "Parsed file contain errors (at line 2).");
+        let error = ParseError {
+            diagnostics: vec![],
+        };
+        assert_eq!(error.to_string(), "Parsed file contain errors.");
+    }
+}
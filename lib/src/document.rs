@@ -0,0 +1,223 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use anyhow::{anyhow, Result};
+use tree_sitter::{InputEdit, Parser, Point, Tree};
+
+use crate::beautifier::{check_tree, format_tree, new_parser, ItemCache};
+use crate::{detect_line_ending, Diagnostic, LineEnding, ParseError, Settings, TextEdit};
+
+/// A ledger file that is formatted repeatedly while it is edited, as in an editor. Edits reparse
+/// only the part of the file they touch, and formatting only formats the items that changed since
+/// the last time.
+pub struct Document {
+    parser: Parser,
+    tree: Tree,
+    /// The text of the document, followed by the line break the parser expects at its end.
+    source: String,
+    settings: Settings,
+    cache: ItemCache,
+}
+
+impl Document {
+    pub fn new(text: &str, settings: Settings) -> Result<Self> {
+        let mut parser = new_parser()?;
+        let source = text.to_string() + "\n";
+        let tree = parser
+            .parse(&source, None)
+            .ok_or_else(|| anyhow!("Could not parse file."))?;
+        Ok(Document {
+            parser,
+            tree,
+            source,
+            settings,
+            cache: ItemCache::default(),
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.source[..self.source.len() - 1]
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Changes the settings the document is formatted with.
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+        self.cache = ItemCache::default();
+    }
+
+    /// Applies `edit`, a byte range of the current text and its replacement, and reparses the
+    /// document reusing the unchanged parts of the previous tree.
+    pub fn edit(&mut self, edit: &TextEdit) -> Result<()> {
+        let range = &edit.range;
+        let text = self.text();
+        if range.start > range.end
+            || range.end > text.len()
+            || !text.is_char_boundary(range.start)
+            || !text.is_char_boundary(range.end)
+        {
+            return Err(anyhow!(
+                "Edit {}..{} is not a valid range of the document.",
+                range.start,
+                range.end
+            ));
+        }
+        let start_position = point(&self.source, range.start);
+        let old_end_position = point(&self.source, range.end);
+        self.source.replace_range(range.clone(), &edit.new_text);
+        let new_end_byte = range.start + edit.new_text.len();
+        self.tree.edit(&InputEdit {
+            start_byte: range.start,
+            old_end_byte: range.end,
+            new_end_byte,
+            start_position,
+            old_end_position,
+            new_end_position: point(&self.source, new_end_byte),
+        });
+        self.tree = self
+            .parser
+            .parse(&self.source, Some(&self.tree))
+            .ok_or_else(|| anyhow!("Could not parse file."))?;
+        Ok(())
+    }
+
+    /// Replaces the whole text of the document. Only the part between what the old and new text
+    /// start and end with is reparsed.
+    pub fn set_text(&mut self, text: String) -> Result<()> {
+        let old = self.text();
+        let prefix: usize = old
+            .chars()
+            .zip(text.chars())
+            .take_while(|(a, b)| a == b)
+            .map(|(c, _)| c.len_utf8())
+            .sum();
+        let suffix: usize = old[prefix..]
+            .chars()
+            .rev()
+            .zip(text[prefix..].chars().rev())
+            .take_while(|(a, b)| a == b)
+            .map(|(c, _)| c.len_utf8())
+            .sum();
+        if prefix == old.len() && prefix == text.len() {
+            return Ok(());
+        }
+        self.edit(&TextEdit {
+            range: prefix..old.len() - suffix,
+            new_text: text[prefix..text.len() - suffix].to_string(),
+        })
+    }
+
+    /// The syntax errors of the current text.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match check_tree(&self.tree, &self.source) {
+            Ok(()) => vec![],
+            Err(err) => err
+                .downcast::<ParseError>()
+                .map(|err| err.diagnostics)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Formats the current text, like `beautify`.
+    pub fn format(&mut self) -> Result<String> {
+        // The line break added for the parser must not decide the line ending.
+        let settings = Settings {
+            line_ending: match self.settings.line_ending {
+                LineEnding::Auto if detect_line_ending(self.text()) == "\r\n" => LineEnding::Crlf,
+                LineEnding::Auto => LineEnding::Lf,
+                line_ending => line_ending,
+            },
+            ..self.settings.clone()
+        };
+        format_tree(&self.source, &self.tree, &settings, &mut self.cache)
+    }
+}
+
+/// The row and byte column of the byte `offset` of `text`, as tree-sitter counts them.
+fn point(text: &str, offset: usize) -> Point {
+    let before = &text[..offset];
+    match before.rfind('\n') {
+        Some(newline) => Point::new(before.matches('\n').count(), offset - newline - 1),
+        None => Point::new(0, offset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beautify;
+    use std::ops::Range;
+
+    const JOURNAL: &str = "2024/01/02 Shop\n    Expenses:Food    10 EUR\n    Assets:Cash\n\n\
+                           2024/01/01 Salary\n  Assets:Bank   100 EUR\n  Income:Salary\n";
+
+    #[test]
+    fn counts_points_like_tree_sitter() {
+        assert_eq!(point("ab\ncd", 0), Point::new(0, 0));
+        assert_eq!(point("ab\ncd", 2), Point::new(0, 2));
+        assert_eq!(point("ab\ncd", 3), Point::new(1, 0));
+        assert_eq!(point("ab\ncd", 5), Point::new(1, 2));
+    }
+
+    #[test]
+    fn formats_after_edits_like_beautify() {
+        let settings = Settings::default();
+        let mut document = Document::new(JOURNAL, settings.clone()).unwrap();
+        assert_eq!(
+            document.format().unwrap(),
+            beautify(JOURNAL, &settings).unwrap()
+        );
+
+        let offset = JOURNAL.find("10 EUR").unwrap();
+        document
+            .edit(&TextEdit {
+                range: offset..offset + 2,
+                new_text: "12".to_string(),
+            })
+            .unwrap();
+        let edited = JOURNAL.replace("10 EUR", "12 EUR");
+        assert_eq!(document.text(), edited);
+        assert_eq!(
+            document.format().unwrap(),
+            beautify(&edited, &settings).unwrap()
+        );
+
+        let replaced = edited.replace("Shop", "Market");
+        document.set_text(replaced.clone()).unwrap();
+        assert_eq!(document.text(), replaced);
+        assert_eq!(
+            document.format().unwrap(),
+            beautify(&replaced, &settings).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_edits() {
+        let mut document = Document::new("; é\n", Settings::default()).unwrap();
+        for range in [3..4, Range { start: 2, end: 1 }, 0..10] {
+            let edit = TextEdit {
+                range,
+                new_text: String::new(),
+            };
+            assert!(document.edit(&edit).is_err());
+        }
+        assert_eq!(document.text(), "; é\n");
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let mut document = Document::new(JOURNAL, Settings::default()).unwrap();
+        assert!(document.diagnostics().is_empty());
+        document
+            .set_text("2024/01/01 Shop\n  Expenses:Food  10 EUR EUR EUR\n(".to_string())
+            .unwrap();
+        assert!(!document.diagnostics().is_empty());
+        document.set_text(JOURNAL.to_string()).unwrap();
+        assert!(document.diagnostics().is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashSet;
+
+use super::args::DateFormat;
+use super::beautifier::format_date;
+use super::import::ImportRules;
+use super::journal::{Amount, Item, Journal, Transaction};
+use chrono::NaiveDate;
+use serde::Serialize;
+
+/// The metadata tags that identify a transaction of a statement.
+const ID_TAGS: [&str; 2] = ["fitid", "uuid"];
+
+/// An imported transaction that was left out because the journal already has it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Skipped {
+    pub transaction: Transaction,
+    /// Why the transaction was taken for one of the journal.
+    pub reason: String,
+    /// The line of the journal's transaction, counting from 1.
+    pub line: Option<usize>,
+}
+
+/// Removes from `imported` the transactions `journal` already has. They are recognized by a
+/// `FITID` or `uuid` tag with the same value or, when either lacks it, by a close date, the same
+/// amount and a similar payee.
+pub fn remove_duplicates(
+    imported: Journal,
+    journal: &Journal,
+    rules: &ImportRules,
+) -> (Journal, Vec<Skipped>) {
+    let existing: Vec<Known> = journal
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Transaction(transaction) => Some(Known::new(transaction)),
+            _ => None,
+        })
+        .collect();
+    let mut items = vec![];
+    let mut skipped = vec![];
+    for item in imported.items {
+        let Item::Transaction(transaction) = item else {
+            items.push(item);
+            continue;
+        };
+        let incoming = Known::new(&transaction);
+        match existing
+            .iter()
+            .find_map(|known| known.matches(&incoming, rules))
+        {
+            Some((reason, line)) => skipped.push(Skipped {
+                transaction,
+                reason,
+                line,
+            }),
+            None => items.push(Item::Transaction(transaction)),
+        }
+    }
+    (Journal { items }, skipped)
+}
+
+/// The parts of a transaction duplicates are looked for by.
+struct Known {
+    line: Option<usize>,
+    date: Option<NaiveDate>,
+    payee: String,
+    /// The absolute values of the amounts of its postings, with their commodity.
+    amounts: HashSet<(String, Option<String>)>,
+    /// `(tag, value)`, with the tag in lowercase.
+    ids: Vec<(String, String)>,
+}
+
+impl Known {
+    fn new(transaction: &Transaction) -> Known {
+        let notes = transaction
+            .notes
+            .iter()
+            .chain(transaction.postings.iter().flat_map(|p| &p.notes));
+        Known {
+            line: transaction.line,
+            date: transaction.date.as_deref().and_then(|date| {
+                NaiveDate::parse_from_str(&format_date(date, DateFormat::Iso), "%Y-%m-%d").ok()
+            }),
+            payee: transaction.payee.clone().unwrap_or_default(),
+            amounts: transaction
+                .postings
+                .iter()
+                .filter_map(|posting| posting.amount.as_ref())
+                .filter_map(absolute)
+                .collect(),
+            ids: notes.flat_map(|note| metadata(note)).collect(),
+        }
+    }
+
+    /// Why `incoming` is taken for this transaction, and its line, if it is.
+    fn matches(&self, incoming: &Known, rules: &ImportRules) -> Option<(String, Option<usize>)> {
+        for (tag, value) in &incoming.ids {
+            if let Some((_, known)) = self.ids.iter().find(|(known, _)| known == tag) {
+                return (known == value).then(|| {
+                    (
+                        format!("the journal has a transaction with {tag} {value}"),
+                        self.line,
+                    )
+                });
+            }
+        }
+        let days = (self.date? - incoming.date?).num_days().unsigned_abs();
+        if days > u64::from(rules.duplicate_window) {
+            return None;
+        }
+        let amount = incoming
+            .amounts
+            .iter()
+            .find(|amount| self.amounts.contains(amount))?;
+        let similarity = similarity(&self.payee, &incoming.payee);
+        if similarity < rules.duplicate_similarity {
+            return None;
+        }
+        Some((
+            format!(
+                "the journal has a transaction {days} day(s) apart for {}{} to a payee {:.0}% similar",
+                amount.0,
+                amount.1.as_deref().map(|c| format!(" {c}")).unwrap_or_default(),
+                similarity * 100.0
+            ),
+            self.line,
+        ))
+    }
+}
+
+/// The value of `amount` without its sign, and its commodity.
+fn absolute(amount: &Amount) -> Option<(String, Option<String>)> {
+    let value = amount.value.as_deref()?.trim_start_matches('-');
+    // `10.50` and `10.5` are the same amount.
+    let value = match value.contains('.') {
+        true => value.trim_end_matches('0').trim_end_matches('.'),
+        false => value,
+    };
+    Some((value.to_string(), amount.commodity.clone()))
+}
+
+/// Reads the `tag: value` metadata of `note` whose tag is one of `ID_TAGS`.
+fn metadata(note: &str) -> Option<(String, String)> {
+    let (tag, value) = note.trim_start_matches(';').split_once(':')?;
+    let tag = tag.trim().to_lowercase();
+    let value = value.trim();
+    (ID_TAGS.contains(&tag.as_str()) && !value.is_empty()).then(|| (tag, value.to_string()))
+}
+
+/// The Sørensen–Dice coefficient of the letter pairs of `a` and `b`, ignoring case and anything
+/// that is not a letter or digit.
+fn similarity(a: &str, b: &str) -> f64 {
+    let pairs = |text: &str| -> Vec<(char, char)> {
+        let chars: Vec<char> = text
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect();
+        chars.windows(2).map(|pair| (pair[0], pair[1])).collect()
+    };
+    let (a, mut b) = (pairs(a), pairs(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let total = a.len() + b.len();
+    let mut common = 0;
+    for pair in a {
+        if let Some(index) = b.iter().position(|other| *other == pair) {
+            b.swap_remove(index);
+            common += 1;
+        }
+    }
+    2.0 * common as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::{parse_amount, Posting};
+
+    fn rules() -> ImportRules {
+        toml::from_str(r#"default-account = "Expenses:Unknown""#).unwrap()
+    }
+
+    fn transaction(date: &str, payee: &str, amount: &str, notes: &[&str]) -> Transaction {
+        Transaction {
+            line: Some(1),
+            date: Some(date.to_string()),
+            payee: Some(payee.to_string()),
+            notes: notes.iter().map(ToString::to_string).collect(),
+            postings: vec![
+                Posting {
+                    account: "Assets:Checking".to_string(),
+                    amount: Some(parse_amount(amount, None)),
+                    ..Default::default()
+                },
+                Posting {
+                    account: "Expenses:Food".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn journal(transactions: Vec<Transaction>) -> Journal {
+        Journal {
+            items: transactions.into_iter().map(Item::Transaction).collect(),
+        }
+    }
+
+    fn payees(journal: &Journal) -> Vec<&str> {
+        journal
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Transaction(transaction) => transaction.payee.as_deref(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_transactions_with_the_same_id() {
+        let known = journal(vec![transaction(
+            "2024/01/01",
+            "Bakery",
+            "-5 EUR",
+            &["; FITID: 1"],
+        )]);
+        let imported = journal(vec![
+            transaction("2024-03-01", "Other", "-9 EUR", &["; fitid: 1"]),
+            transaction("2024-01-01", "Bakery", "-5 EUR", &["; FITID: 2"]),
+        ]);
+        let (kept, skipped) = remove_duplicates(imported, &known, &rules());
+        assert_eq!(payees(&kept), ["Bakery"]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(
+            skipped[0].reason,
+            "the journal has a transaction with fitid 1"
+        );
+        assert_eq!(skipped[0].line, Some(1));
+    }
+
+    #[test]
+    fn skips_similar_transactions_without_ids() {
+        let known = journal(vec![transaction(
+            "2024/01/01",
+            "Bakery Smith",
+            "-5.50 EUR",
+            &[],
+        )]);
+        let imported = journal(vec![
+            transaction("2024-01-03", "BAKERY SMITH 1234", "-5.5 EUR", &[]),
+            transaction("2024-01-10", "Bakery Smith", "-5.50 EUR", &[]),
+            transaction("2024-01-01", "Bakery Smith", "-6 EUR", &[]),
+            transaction("2024-01-01", "Gas station", "-5.50 EUR", &[]),
+        ]);
+        let (kept, skipped) = remove_duplicates(imported, &known, &rules());
+        assert_eq!(
+            payees(&kept),
+            ["Bakery Smith", "Bakery Smith", "Gas station"]
+        );
+        assert_eq!(skipped.len(), 1);
+        assert!(skipped[0]
+            .reason
+            .starts_with("the journal has a transaction 2 day(s) apart"));
+    }
+
+    #[test]
+    fn compares_payees_by_letter_pairs() {
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("Night", "NIGHT!"), 1.0);
+        assert_eq!(similarity("abc", "xyz"), 0.0);
+        assert!((similarity("night", "nacht") - 0.25).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,188 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use similar::{DiffOp, TextDiff};
+
+/// Replaces the bytes `range` of a text with `new_text`. The edits returned together never
+/// overlap and are sorted by their position, all of them relative to the original text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub new_text: String,
+}
+
+/// How long the diffs of `minimal_edits` may take. Past it, they give up on finding the smallest
+/// edits and return larger ones, which are still correct.
+const DIFF_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Runs of differing lines longer than this, in bytes, are replaced whole instead of being compared
+/// character by character.
+const REFINE_LIMIT: usize = 16 * 1024;
+
+/// The smallest edits that turn `original` into `formatted`, so editors can keep the cursor, marks
+/// and undo history of the parts that do not change. Lines are compared first, and the lines that
+/// differ are compared character by character.
+pub fn minimal_edits(original: &str, formatted: &str) -> Vec<TextEdit> {
+    let mut edits = vec![];
+    let deadline = Instant::now() + DIFF_TIMEOUT;
+    let lines = TextDiff::configure()
+        .deadline(deadline)
+        .diff_lines(original, formatted);
+    let old_starts = slice_starts(lines.old_slices());
+    let new_starts = slice_starts(lines.new_slices());
+    for op in lines.ops() {
+        if matches!(op, DiffOp::Equal { .. }) {
+            continue;
+        }
+        let old = old_starts[op.old_range().start]..old_starts[op.old_range().end];
+        let new = new_starts[op.new_range().start]..new_starts[op.new_range().end];
+        if old.len() + new.len() > REFINE_LIMIT {
+            edits.push(TextEdit {
+                range: old,
+                new_text: formatted[new].to_string(),
+            });
+            continue;
+        }
+        refine(
+            &original[old.clone()],
+            &formatted[new],
+            old.start,
+            deadline,
+            &mut edits,
+        );
+    }
+    edits
+}
+
+/// Pushes the edits that turn `old` into `new`, character by character. `offset` is where `old`
+/// starts in the original text.
+fn refine(old: &str, new: &str, offset: usize, deadline: Instant, edits: &mut Vec<TextEdit>) {
+    let chars = TextDiff::configure()
+        .deadline(deadline)
+        .diff_chars(old, new);
+    let old_starts = slice_starts(chars.old_slices());
+    let new_starts = slice_starts(chars.new_slices());
+    // Consecutive deletions and insertions are merged into a single replacement.
+    let mut pending: Option<TextEdit> = None;
+    for op in chars.ops() {
+        if matches!(op, DiffOp::Equal { .. }) {
+            edits.extend(pending.take());
+            continue;
+        }
+        let range =
+            offset + old_starts[op.old_range().start]..offset + old_starts[op.old_range().end];
+        let new_text = &new[new_starts[op.new_range().start]..new_starts[op.new_range().end]];
+        match &mut pending {
+            Some(edit) => {
+                edit.range.end = range.end;
+                edit.new_text += new_text;
+            }
+            None => {
+                pending = Some(TextEdit {
+                    range,
+                    new_text: new_text.to_string(),
+                })
+            }
+        }
+    }
+    edits.extend(pending);
+}
+
+/// The byte offset each of `slices` starts at, followed by the end of the last one.
+fn slice_starts(slices: &[&str]) -> Vec<usize> {
+    let mut starts = Vec::with_capacity(slices.len() + 1);
+    let mut offset = 0;
+    starts.push(offset);
+    for slice in slices {
+        offset += slice.len();
+        starts.push(offset);
+    }
+    starts
+}
+
+/// Applies `edits` to `code`.
+pub fn apply_edits(code: &str, edits: &[TextEdit]) -> String {
+    let mut result = String::with_capacity(code.len());
+    let mut last = 0;
+    for edit in edits {
+        result += &code[last..edit.range.start];
+        result += &edit.new_text;
+        last = edit.range.end;
+    }
+    result += &code[last..];
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_applies(original: &str, formatted: &str) -> Vec<TextEdit> {
+        let edits = minimal_edits(original, formatted);
+        assert_eq!(apply_edits(original, &edits), formatted);
+        edits
+    }
+
+    #[test]
+    fn edits_only_what_changed() {
+        let original = "2024/01/01 Shop\n    Expenses:Food    10 EUR\n    Assets:Cash\n";
+        let formatted = "2024/01/01 Shop\n  Expenses:Food  10 EUR\n  Assets:Cash\n";
+        let edits = assert_applies(original, formatted);
+        assert!(
+            edits
+                .iter()
+                .all(|edit| edit.new_text.is_empty()
+                    && original[edit.range.clone()].trim().is_empty())
+        );
+        let removed: usize = edits.iter().map(|edit| edit.range.len()).sum();
+        assert_eq!(removed, 6);
+    }
+
+    #[test]
+    fn merges_replacements() {
+        let edits = assert_applies("a = 1\n", "a == 10\n");
+        assert_eq!(
+            edits,
+            [
+                TextEdit {
+                    range: 3..3,
+                    new_text: "=".to_string(),
+                },
+                TextEdit {
+                    range: 5..5,
+                    new_text: "0".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_empty_and_unchanged_texts() {
+        assert!(assert_applies("same\n", "same\n").is_empty());
+        assert_applies("", "new\n");
+        assert_applies("old\n", "");
+        assert_applies("ünïcödé\n", "unicode\n");
+    }
+
+    #[test]
+    fn diffs_large_reordered_texts_quickly() {
+        let lines: Vec<String> = (0..20_000)
+            .map(|n| format!("2024/01/01 Payee {n}\n    Expenses:Food  {n} EUR\n"))
+            .collect();
+        let original = lines.concat();
+        let formatted: String = lines
+            .iter()
+            .rev()
+            .map(|line| line.replace("    ", "  "))
+            .collect();
+        let start = Instant::now();
+        assert_applies(&original, &formatted);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
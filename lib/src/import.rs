@@ -0,0 +1,693 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use super::journal::{parse_amount, Amount, Item, Journal, Posting, Side, Transaction};
+use super::number::add_quantities;
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+/// How the rows of a bank's CSV file are turned into transactions.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ImportRules {
+    /// Field separator. [default: `,`]
+    #[serde(default)]
+    pub delimiter: Option<char>,
+    /// Lines to ignore before the header, or before the first row when there is no header.
+    #[serde(default)]
+    pub skip: usize,
+    /// Whether the first row names the columns. [default: true]
+    #[serde(default = "default_true")]
+    pub header: bool,
+    /// The format of the date column, as understood by chrono, like `%d/%m/%Y`. [default: `%Y-%m-%d`]
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    /// The decimal mark of the amounts, guessed when not set.
+    #[serde(default)]
+    pub decimal_mark: Option<char>,
+    /// Whether the amounts are written from the other side, positive for money leaving the account,
+    /// as in credit card statements. Applies to the `amount` column and to the `debit` and `credit`
+    /// columns alike.
+    #[serde(default)]
+    pub invert_sign: bool,
+    /// The commodity of amounts that are written without one.
+    #[serde(default)]
+    pub currency: Option<String>,
+    /// The status of the generated transactions, like `*`.
+    #[serde(default)]
+    pub status: Option<String>,
+    /// The account the file belongs to. For OFX statements, used when `accounts` has no entry for
+    /// the statement's account.
+    #[serde(default)]
+    pub account: Option<String>,
+    /// The other side of transactions no rule matches.
+    pub default_account: String,
+    /// The columns of CSV statements.
+    #[serde(default)]
+    pub columns: Option<Columns>,
+    /// Maps the account ids of OFX statements (`ACCTID`) to ledger accounts.
+    #[serde(default)]
+    pub accounts: HashMap<String, String>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// How many days apart a transaction of the journal can be from an imported one and still be
+    /// taken for the same, when neither has an id. [default: 3]
+    #[serde(default = "default_duplicate_window")]
+    pub duplicate_window: u32,
+    /// How similar, from 0 to 1, payees must be for transactions to be taken for the same.
+    /// [default: 0.6]
+    #[serde(default = "default_duplicate_similarity")]
+    pub duplicate_similarity: f64,
+    /// Whether the other side of transactions no rule matches is guessed from the journal.
+    #[serde(default)]
+    pub classify: bool,
+    /// How likely, from 0 to 1, a guess must be to not be tagged for review. [default: 0.8]
+    #[serde(default = "default_classify_confidence")]
+    pub classify_confidence: f64,
+    /// The tag of transactions whose guessed account should be reviewed. [default: `review`]
+    #[serde(default = "default_review_tag")]
+    pub review_tag: String,
+}
+
+/// Where each field is read from, as a column name of the header or a position counting from 1.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Columns {
+    pub date: Column,
+    pub payee: Option<Column>,
+    /// The signed amount. Use `debit` and `credit` instead for files that split it in two.
+    pub amount: Option<Column>,
+    /// Money leaving the account.
+    pub debit: Option<Column>,
+    /// Money entering the account.
+    pub credit: Option<Column>,
+    pub code: Option<Column>,
+    pub note: Option<Column>,
+    /// The commodity of each row, taking precedence over `currency`.
+    pub currency: Option<Column>,
+    /// A unique id of the row, kept as a `uuid` tag to recognize it when it is imported again.
+    pub id: Option<Column>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Column {
+    Position(usize),
+    Name(String),
+}
+
+/// Assigns the other side, and possibly a new payee, to the transactions whose `field` matches.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Rule {
+    #[serde(rename = "match", deserialize_with = "regex")]
+    pub pattern: Regex,
+    /// The field `match` is tested against: `payee`, `note` or `code`. [default: `payee`]
+    #[serde(default)]
+    pub field: RuleField,
+    pub account: Option<String>,
+    pub payee: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleField {
+    #[default]
+    Payee,
+    Note,
+    Code,
+}
+
+impl ImportRules {
+    /// Reads and parses the rules file at `path`.
+    pub fn load(path: &Path) -> Result<ImportRules> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let rules: ImportRules = toml::from_str(&contents)
+            .with_context(|| format!("Could not parse {}", path.display()))?;
+        let Some(columns) = &rules.columns else {
+            return Ok(rules);
+        };
+        if columns.amount.is_none() && columns.debit.is_none() && columns.credit.is_none() {
+            return Err(anyhow!(
+                "{} must set the amount column, or the debit and credit columns",
+                path.display()
+            ));
+        }
+        Ok(rules)
+    }
+
+    /// The accounts statements are imported into.
+    pub fn statement_accounts(&self) -> HashSet<&str> {
+        self.account
+            .iter()
+            .chain(self.accounts.values())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Turns `entry` into a balanced transaction, with the other side and payee given by the first
+    /// rule that matches it.
+    pub(crate) fn transaction(&self, entry: Entry) -> Transaction {
+        let Entry {
+            account,
+            date,
+            payee,
+            code,
+            note,
+            amount,
+            tags,
+        } = entry;
+        let [payee, code, note] = [payee, code, note].map(|text| {
+            let text = single_line(&text?);
+            (!text.is_empty()).then_some(text)
+        });
+        // A `;` would start a note on the transaction's first line.
+        let [mut payee, code] = [payee, code].map(|text| text.map(|text| text.replace(';', ",")));
+        let mut counter_account = self.default_account.clone();
+        for rule in &self.rules {
+            let field = match rule.field {
+                RuleField::Payee => &payee,
+                RuleField::Note => &note,
+                RuleField::Code => &code,
+            };
+            if !field
+                .as_deref()
+                .is_some_and(|field| rule.pattern.is_match(field))
+            {
+                continue;
+            }
+            if let Some(rule_account) = &rule.account {
+                counter_account = rule_account.clone();
+            }
+            if let Some(rule_payee) = &rule.payee {
+                payee = Some(rule_payee.clone());
+            }
+            break;
+        }
+        let tags = tags
+            .into_iter()
+            .map(|(name, value)| format!("; {name}: {}", single_line(&value)));
+        Transaction {
+            date: Some(date.format("%Y-%m-%d").to_string()),
+            status: self.status.clone(),
+            code: code.map(|code| format!("({code})")),
+            payee,
+            notes: note
+                .map(|note| format!("; {note}"))
+                .into_iter()
+                .chain(tags)
+                .collect(),
+            postings: vec![
+                Posting {
+                    account,
+                    amount: Some(amount.clone()),
+                    ..Default::default()
+                },
+                Posting {
+                    account: counter_account,
+                    amount: Some(negate(amount)),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// Writes the quantity of `amount` as a plain number, and sets its commodity to `currency`, or
+    /// the configured one, when it has none.
+    pub(crate) fn amount(&self, mut amount: Amount, currency: Option<String>) -> Amount {
+        amount.quantity = amount.value.clone().unwrap_or_default();
+        let currency = currency.or_else(|| self.currency.clone());
+        if amount.commodity.is_none() && currency.is_some() {
+            amount.commodity = currency;
+            amount.side = Some(Side::Right);
+        }
+        amount
+    }
+}
+
+/// `text` on a single line, with runs of whitespace collapsed, so a statement cannot add lines,
+/// postings or notes to the transactions it is imported into.
+fn single_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// A statement entry, before the rules assign its other side.
+pub(crate) struct Entry {
+    /// The account of the statement.
+    pub account: String,
+    pub date: NaiveDate,
+    pub payee: Option<String>,
+    pub code: Option<String>,
+    pub note: Option<String>,
+    /// The amount entering `account`.
+    pub amount: Amount,
+    /// Metadata written as `; name: value` notes, like the `FITID` of OFX transactions.
+    pub tags: Vec<(&'static str, String)>,
+}
+
+/// Reads the bank statement `csv` into a journal with one balanced transaction per row.
+pub fn import_csv(csv: &str, rules: &ImportRules) -> Result<Journal> {
+    let columns = rules
+        .columns
+        .as_ref()
+        .ok_or_else(|| anyhow!("the rules do not describe the columns of CSV statements"))?;
+    let account = rules
+        .account
+        .as_ref()
+        .ok_or_else(|| anyhow!("the rules do not set the account of CSV statements"))?;
+    let delimiter = u8::try_from(rules.delimiter.unwrap_or(','))
+        .map_err(|_| anyhow!("the delimiter must be a single ASCII character"))?;
+    let body = csv.lines().skip(rules.skip).collect::<Vec<_>>().join("\n");
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(rules.header)
+        .flexible(true)
+        .from_reader(body.as_bytes());
+    let headers = if rules.header {
+        reader.headers()?.iter().map(str::to_string).collect()
+    } else {
+        vec![]
+    };
+    let mut items = vec![];
+    for (index, record) in reader.records().enumerate() {
+        let row = rules.skip + index + usize::from(rules.header) + 1;
+        let record = record?;
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let fields = Row {
+            rules,
+            columns,
+            headers: &headers,
+            record: &record,
+        };
+        let entry = fields
+            .entry(account)
+            .with_context(|| format!("Could not import row {row}"))?;
+        items.push(Item::Transaction(rules.transaction(entry)));
+    }
+    Ok(Journal { items })
+}
+
+struct Row<'a> {
+    rules: &'a ImportRules,
+    columns: &'a Columns,
+    headers: &'a [String],
+    record: &'a csv::StringRecord,
+}
+
+impl Row<'_> {
+    fn get(&self, column: Option<&Column>) -> Result<Option<String>> {
+        let Some(column) = column else {
+            return Ok(None);
+        };
+        let index = match column {
+            Column::Position(position) => position.checked_sub(1),
+            Column::Name(name) => self.headers.iter().position(|header| header.trim() == name),
+        };
+        let index = index.ok_or_else(|| anyhow!("there is no column {column}"))?;
+        let value = self.record.get(index).unwrap_or_default().trim();
+        Ok((!value.is_empty()).then(|| value.to_string()))
+    }
+
+    fn entry(&self, account: &str) -> Result<Entry> {
+        let rules = self.rules;
+        let columns = self.columns;
+        let date = self
+            .get(Some(&columns.date))?
+            .ok_or_else(|| anyhow!("the date is empty"))?;
+        let date = NaiveDate::parse_from_str(&date, &rules.date_format)
+            .with_context(|| format!("`{date}` does not match `{}`", rules.date_format))?;
+        Ok(Entry {
+            account: account.to_string(),
+            date,
+            payee: self.get(columns.payee.as_ref())?,
+            code: self.get(columns.code.as_ref())?,
+            note: self.get(columns.note.as_ref())?,
+            amount: self.amount()?,
+            tags: self
+                .get(columns.id.as_ref())?
+                .map(|id| ("uuid", id))
+                .into_iter()
+                .collect(),
+        })
+    }
+
+    /// The amount entering the account, with its commodity.
+    fn amount(&self) -> Result<Amount> {
+        let columns = self.columns;
+        let read = |column: Option<&Column>| -> Result<Option<Amount>> {
+            let Some(text) = self.get(column)? else {
+                return Ok(None);
+            };
+            let amount = parse_amount(&text, self.rules.decimal_mark);
+            if amount.value.is_none() {
+                return Err(anyhow!("`{text}` is not an amount"));
+            }
+            Ok(Some(amount))
+        };
+        let amount = match read(columns.amount.as_ref())? {
+            Some(amount) => amount,
+            None => {
+                let credit = read(columns.credit.as_ref())?;
+                let debit = read(columns.debit.as_ref())?;
+                if credit.is_none() && debit.is_none() {
+                    return Err(anyhow!("the amount is empty"));
+                }
+                // Statements write the unused column empty or as zero, and some write debits as
+                // negative numbers, so the amount is the credit minus the debit, both unsigned.
+                let value = |amount: &Option<Amount>| {
+                    let value = amount.as_ref().and_then(|amount| amount.value.as_deref());
+                    value.unwrap_or("0").trim_start_matches('-').to_string()
+                };
+                let total = add_quantities(&value(&credit), &negate_number(&value(&debit)))
+                    .ok_or_else(|| anyhow!("the amount is too large"))?;
+                let commodity = [credit, debit]
+                    .into_iter()
+                    .flatten()
+                    .find(|amount| amount.commodity.is_some());
+                Amount {
+                    quantity: total.clone(),
+                    value: Some(total),
+                    side: commodity.as_ref().and_then(|amount| amount.side),
+                    commodity: commodity.and_then(|amount| amount.commodity),
+                }
+            }
+        };
+        let amount = if self.rules.invert_sign {
+            negate(amount)
+        } else {
+            amount
+        };
+        let currency = self.get(columns.currency.as_ref())?;
+        Ok(self.rules.amount(amount, currency))
+    }
+}
+
+/// Flips the sign of `amount`.
+pub(crate) fn negate(mut amount: Amount) -> Amount {
+    amount.quantity = negate_number(&amount.quantity);
+    amount.value = amount.value.as_deref().map(negate_number);
+    amount
+}
+
+fn negate_number(number: &str) -> String {
+    match number.strip_prefix('-') {
+        Some(positive) => positive.to_string(),
+        None => format!("-{number}"),
+    }
+}
+
+impl std::fmt::Display for Column {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Column::Position(position) => write!(f, "{position}"),
+            Column::Name(name) => write!(f, "`{name}`"),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_duplicate_window() -> u32 {
+    3
+}
+
+fn default_duplicate_similarity() -> f64 {
+    0.6
+}
+
+fn default_classify_confidence() -> f64 {
+    0.8
+}
+
+fn default_review_tag() -> String {
+    "review".to_string()
+}
+
+fn default_date_format() -> String {
+    "%Y-%m-%d".to_string()
+}
+
+fn regex<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(toml: &str) -> ImportRules {
+        toml::from_str(toml).unwrap()
+    }
+
+    /// The date, payee and amount of each imported transaction.
+    fn rows(journal: &Journal) -> Vec<(String, String, String)> {
+        journal
+            .items
+            .iter()
+            .map(|item| {
+                let Item::Transaction(transaction) = item else {
+                    panic!("expected a transaction");
+                };
+                (
+                    transaction.date.clone().unwrap_or_default(),
+                    transaction.payee.clone().unwrap_or_default(),
+                    transaction.postings[0]
+                        .amount
+                        .as_ref()
+                        .map(ToString::to_string)
+                        .unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    fn row(date: &str, payee: &str, amount: &str) -> (String, String, String) {
+        (date.to_string(), payee.to_string(), amount.to_string())
+    }
+
+    #[test]
+    fn reads_columns_by_name() {
+        let rules = rules(
+            r#"
+            account = "Assets:Checking"
+            default-account = "Expenses:Unknown"
+            currency = "EUR"
+            [columns]
+            date = "Date"
+            payee = "Description"
+            amount = "Amount"
+            "#,
+        );
+        let csv = "Amount,Description,Date\n-12.50,Bakery,2024-03-01\n1000,Salary,2024-03-02\n";
+        let journal = import_csv(csv, &rules).unwrap();
+        assert_eq!(
+            rows(&journal),
+            [
+                row("2024-03-01", "Bakery", "-12.50 EUR"),
+                row("2024-03-02", "Salary", "1000 EUR"),
+            ]
+        );
+        let Item::Transaction(transaction) = &journal.items[0] else {
+            unreachable!()
+        };
+        assert_eq!(transaction.postings[0].account, "Assets:Checking");
+        assert_eq!(transaction.postings[1].account, "Expenses:Unknown");
+        assert_eq!(
+            transaction.postings[1].amount.as_ref().unwrap().quantity,
+            "12.50"
+        );
+    }
+
+    #[test]
+    fn reads_columns_by_position_with_date_format_and_decimal_mark() {
+        let rules = rules(
+            r#"
+            skip = 2
+            header = false
+            delimiter = ";"
+            date-format = "%d/%m/%Y"
+            decimal-mark = ","
+            account = "Assets:Checking"
+            default-account = "Expenses:Unknown"
+            [columns]
+            date = 1
+            payee = 3
+            amount = 2
+            currency = 4
+            "#,
+        );
+        let csv = "Bank export\nAccount 123\n31/01/2024;-1.234,56;Rent;USD\n";
+        let journal = import_csv(csv, &rules).unwrap();
+        assert_eq!(rows(&journal), [row("2024-01-31", "Rent", "-1234.56 USD")]);
+    }
+
+    #[test]
+    fn rejects_dates_that_do_not_match_the_format() {
+        let rules = rules(
+            r#"
+            account = "Assets:Checking"
+            default-account = "Expenses:Unknown"
+            [columns]
+            date = "Date"
+            amount = "Amount"
+            "#,
+        );
+        let error = import_csv("Date,Amount\n01/02/2024,10\n", &rules).unwrap_err();
+        assert!(format!("{error:#}").contains("row 2"), "{error:#}");
+    }
+
+    #[test]
+    fn subtracts_debits_from_credits() {
+        let toml = r#"
+            account = "Assets:Checking"
+            default-account = "Expenses:Unknown"
+            [columns]
+            date = "Date"
+            payee = "Payee"
+            debit = "Debit"
+            credit = "Credit"
+            "#;
+        let csv = "Date,Payee,Debit,Credit
+2024-01-01,Shop,25.00,0.00
+2024-01-02,Refund,,5.5
+2024-01-03,Fee,-1.20,
+2024-01-04,Both,2.00,3.00
+";
+        let journal = import_csv(csv, &rules(toml)).unwrap();
+        assert_eq!(
+            rows(&journal),
+            [
+                row("2024-01-01", "Shop", "-25.00"),
+                row("2024-01-02", "Refund", "5.5"),
+                row("2024-01-03", "Fee", "-1.20"),
+                row("2024-01-04", "Both", "1.00"),
+            ]
+        );
+
+        let inverted = rules(&format!("invert-sign = true\n{toml}"));
+        let journal = import_csv(csv, &inverted).unwrap();
+        assert_eq!(rows(&journal)[0], row("2024-01-01", "Shop", "25.00"));
+        assert_eq!(rows(&journal)[1], row("2024-01-02", "Refund", "-5.5"));
+    }
+
+    #[test]
+    fn inverts_the_amount_column() {
+        let rules = rules(
+            r#"
+            invert-sign = true
+            account = "Liabilities:Card"
+            default-account = "Expenses:Unknown"
+            [columns]
+            date = "Date"
+            amount = "Amount"
+            "#,
+        );
+        let journal = import_csv("Date,Amount\n2024-01-01,30\n2024-01-02,-10\n", &rules).unwrap();
+        let amounts: Vec<_> = rows(&journal).into_iter().map(|row| row.2).collect();
+        assert_eq!(amounts, ["-30", "10"]);
+    }
+
+    #[test]
+    fn applies_the_first_matching_rule() {
+        let rules = rules(
+            r#"
+            account = "Assets:Checking"
+            default-account = "Expenses:Unknown"
+            [columns]
+            date = "Date"
+            payee = "Payee"
+            amount = "Amount"
+            id = "Id"
+            [[rules]]
+            match = "(?i)bakery"
+            account = "Expenses:Food"
+            payee = "Bakery"
+            [[rules]]
+            match = "."
+            account = "Expenses:Other"
+            "#,
+        );
+        let csv = "Date,Payee,Amount,Id\n2024-01-01,BAKERY 123,-3,abc\n";
+        let journal = import_csv(csv, &rules).unwrap();
+        let Item::Transaction(transaction) = &journal.items[0] else {
+            unreachable!()
+        };
+        assert_eq!(transaction.payee.as_deref(), Some("Bakery"));
+        assert_eq!(transaction.postings[1].account, "Expenses:Food");
+        assert_eq!(transaction.notes, ["; uuid: abc"]);
+    }
+
+    #[test]
+    fn reads_signs_before_commodities() {
+        let rules = rules(
+            r#"
+            account = "Assets:Checking"
+            default-account = "Expenses:Unknown"
+            [columns]
+            date = "Date"
+            payee = "Payee"
+            amount = "Amount"
+            "#,
+        );
+        let csv = "Date,Payee,Amount\n2024-03-01,Shop,-$12.50\n2024-03-02,Refund,+ $3\n";
+        let journal = import_csv(csv, &rules).unwrap();
+        assert_eq!(
+            rows(&journal),
+            [
+                row("2024-03-01", "Shop", "$-12.50"),
+                row("2024-03-02", "Refund", "$3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_cells_on_one_line() {
+        let rules = rules(
+            r#"
+            account = "Assets:Checking"
+            default-account = "Expenses:Unknown"
+            currency = "EUR"
+            [columns]
+            date = "Date"
+            payee = "Payee"
+            note = "Note"
+            amount = "Amount"
+            "#,
+        );
+        let csv = "Date,Payee,Note,Amount\n\
+                   2024-03-01,\"Shop\n    Expenses:Stolen  100 EUR\",\" a\r\n  ; b \",-1\n\
+                   2024-03-02,Bakery  ; not a note,\"\n\",-2\n";
+        let journal = import_csv(csv, &rules).unwrap();
+        let text = journal.to_string();
+        assert_eq!(
+            text,
+            "2024-03-01 Shop Expenses:Stolen 100 EUR
+    ; a ; b
+    Assets:Checking  -1 EUR
+    Expenses:Unknown  1 EUR
+
+2024-03-02 Bakery , not a note
+    Assets:Checking  -2 EUR
+    Expenses:Unknown  2 EUR
+
+"
+        );
+    }
+}
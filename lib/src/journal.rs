@@ -0,0 +1,846 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt;
+
+use super::beautifier::{beautify, lot_annotation, parse, TraversingError};
+use super::config::Settings;
+use super::number::{decimal_mark, normalize_quantity};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tree_sitter::Node;
+
+/// The contents of a journal, as structured data.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Journal {
+    pub items: Vec<Item>,
+}
+
+/// A top-level entry of a journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Item {
+    Transaction(Transaction),
+    Directive(Directive),
+    Comment(Comment),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    /// A regular, dated transaction.
+    #[default]
+    Plain,
+    /// A `~ interval` budget transaction.
+    Periodic,
+    /// A `= query` automated transaction.
+    Automated,
+}
+
+/// A transaction. Only the fields that apply to its kind are set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    #[serde(default)]
+    pub kind: TransactionKind,
+    /// The line the transaction starts at, counting from 1. Ignored when generating ledger text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective_date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payee: Option<String>,
+    /// The period of a periodic transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    /// The query of an automated transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    /// Notes written before the first posting, including their leading `;`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    #[serde(default)]
+    pub postings: Vec<Posting>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Posting {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    pub account: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Amount>,
+    /// The lot price, written `{}` for the unit cost or `{{}}` for the total cost.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<Price>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<Price>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance_assertion: Option<BalanceAssertion>,
+    /// The note on the posting's line followed by the notes on the lines below it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Amount {
+    /// The quantity as written, like `-1,234.50`.
+    pub quantity: String,
+    /// The quantity as a plain number, like `-1234.50`, when it could be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commodity: Option<String>,
+    /// Which side of the quantity the commodity is written on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub side: Option<Side>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceKind {
+    /// `@`, the price of one unit.
+    #[default]
+    Unit,
+    /// `@@`, the price of the whole amount.
+    Total,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Price {
+    #[serde(default)]
+    pub kind: PriceKind,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceAssertion {
+    /// `=`, or one of hledger's `==`, `=*` and `==*`.
+    #[serde(default = "default_assertion_operator")]
+    pub operator: String,
+    pub amount: Amount,
+}
+
+/// A directive, like `account`, `commodity` or `include`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Directive {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// The directive name, like `account` or `include`.
+    pub kind: String,
+    /// The argument of the directive, like the account name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subdirectives: Vec<Subdirective>,
+}
+
+/// An indented line below an `account`, `commodity` or `tag` directive, like `alias Checking`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subdirective {
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+/// A comment outside of transactions, including its comment characters.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Comment {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    pub text: String,
+}
+
+fn default_assertion_operator() -> String {
+    "=".to_string()
+}
+
+/// Formats `journal` as if it had been read from a ledger file.
+pub fn beautify_journal(journal: &Journal, settings: &Settings) -> Result<String> {
+    beautify(&journal.to_string(), settings)
+}
+
+/// Writes the journal as plain ledger text. The result is valid, but not formatted.
+impl fmt::Display for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            match item {
+                Item::Transaction(transaction) => write!(f, "{transaction}")?,
+                Item::Directive(directive) => write!(f, "{directive}")?,
+                Item::Comment(comment) => writeln!(f, "{}", comment.text)?,
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            TransactionKind::Plain => {
+                write!(f, "{}", self.date.as_deref().unwrap_or_default())?;
+                if let Some(effective_date) = &self.effective_date {
+                    write!(f, "={effective_date}")?;
+                }
+                for field in [&self.status, &self.code, &self.payee]
+                    .into_iter()
+                    .flatten()
+                {
+                    write!(f, " {field}")?;
+                }
+            }
+            TransactionKind::Periodic => {
+                write!(f, "~ {}", self.interval.as_deref().unwrap_or_default())?
+            }
+            TransactionKind::Automated => {
+                write!(f, "= {}", self.query.as_deref().unwrap_or_default())?
+            }
+        }
+        writeln!(f)?;
+        for note in &self.notes {
+            writeln!(f, "    {}", as_note(note))?;
+        }
+        for posting in &self.postings {
+            write!(f, "{posting}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Posting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "    ")?;
+        if let Some(status) = &self.status {
+            write!(f, "{status} ")?;
+        }
+        write!(f, "{}", self.account)?;
+        // Ledger needs two spaces to tell the account from what follows it, whichever comes first.
+        let mut gap = "  ";
+        if let Some(amount) = &self.amount {
+            write!(f, "{gap}{amount}")?;
+            gap = " ";
+        }
+        if let Some(cost) = &self.cost {
+            match cost.kind {
+                PriceKind::Unit => write!(f, "{gap}{{{}}}", cost.amount)?,
+                PriceKind::Total => write!(f, "{gap}{{{{{}}}}}", cost.amount)?,
+            }
+            gap = " ";
+        }
+        if let Some(price) = &self.price {
+            let operator = match price.kind {
+                PriceKind::Unit => "@",
+                PriceKind::Total => "@@",
+            };
+            write!(f, "{gap}{operator} {}", price.amount)?;
+            gap = " ";
+        }
+        if let Some(assertion) = &self.balance_assertion {
+            write!(f, "{gap}{} {}", assertion.operator, assertion.amount)?;
+        }
+        let mut notes = self.notes.iter();
+        if let Some(note) = notes.next() {
+            write!(f, "  {}", as_note(note))?;
+        }
+        writeln!(f)?;
+        for note in notes {
+            writeln!(f, "    {}", as_note(note))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.commodity, self.side) {
+            (None, _) => write!(f, "{}", self.quantity),
+            (Some(commodity), Some(Side::Left)) if commodity.chars().count() == 1 => {
+                write!(f, "{commodity}{}", self.quantity)
+            }
+            (Some(commodity), Some(Side::Left)) => write!(f, "{commodity} {}", self.quantity),
+            (Some(commodity), _) => write!(f, "{} {commodity}", self.quantity),
+        }
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(value) = &self.value {
+            write!(f, " {value}")?;
+        }
+        writeln!(f)?;
+        for subdirective in &self.subdirectives {
+            write!(f, "    {}", subdirective.kind)?;
+            if let Some(value) = &subdirective.value {
+                write!(f, " {value}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// The columns of `Journal::to_csv`.
+const CSV_HEADER: [&str; 12] = [
+    "date",
+    "effective_date",
+    "status",
+    "code",
+    "payee",
+    "posting_status",
+    "account",
+    "quantity",
+    "commodity",
+    "price",
+    "transaction_note",
+    "posting_note",
+];
+
+impl Journal {
+    /// Writes one row per posting of the dated transactions, with the fields of its transaction
+    /// repeated on each row. Quantities are written as plain numbers when they could be read.
+    pub fn to_csv(&self, delimiter: u8) -> Result<String> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_writer(vec![]);
+        writer.write_record(CSV_HEADER)?;
+        for item in &self.items {
+            let Item::Transaction(transaction) = item else {
+                continue;
+            };
+            if transaction.kind != TransactionKind::Plain {
+                continue;
+            }
+            let transaction_note = note_text(&transaction.notes);
+            for posting in &transaction.postings {
+                let amount = posting.amount.as_ref();
+                let price = posting.price.as_ref().map(|price| match price.kind {
+                    PriceKind::Unit => format!("@ {}", price.amount),
+                    PriceKind::Total => format!("@@ {}", price.amount),
+                });
+                writer.write_record([
+                    transaction.date.as_deref().unwrap_or_default(),
+                    transaction.effective_date.as_deref().unwrap_or_default(),
+                    transaction.status.as_deref().unwrap_or_default(),
+                    transaction.code.as_deref().unwrap_or_default(),
+                    transaction.payee.as_deref().unwrap_or_default(),
+                    posting.status.as_deref().unwrap_or_default(),
+                    &posting.account,
+                    amount
+                        .map(|amount| amount.value.as_ref().unwrap_or(&amount.quantity))
+                        .map_or("", String::as_str),
+                    amount
+                        .and_then(|amount| amount.commodity.as_deref())
+                        .unwrap_or_default(),
+                    price.as_deref().unwrap_or_default(),
+                    &transaction_note,
+                    &note_text(&posting.notes),
+                ])?;
+            }
+        }
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+}
+
+/// Joins `notes` into one line per note, without their leading `;`.
+fn note_text(notes: &[String]) -> String {
+    notes
+        .iter()
+        .map(|note| note.trim_start_matches(';').trim())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Adds the `;` that starts a note if `note` does not have it.
+fn as_note(note: &str) -> String {
+    if note.starts_with(';') {
+        note.to_string()
+    } else {
+        format!("; {note}")
+    }
+}
+
+struct Reader<'a> {
+    code: &'a [u8],
+    decimal_mark: Option<char>,
+}
+
+impl Reader<'_> {
+    fn text(&self, node: Node) -> Result<String> {
+        Ok(node.utf8_text(self.code)?.trim().to_string())
+    }
+
+    fn child_text(&self, node: Node, kind: &str) -> Result<Option<String>> {
+        let mut cursor = node.walk();
+        let child = node.named_children(&mut cursor).find(|c| c.kind() == kind);
+        child.map(|child| self.text(child)).transpose()
+    }
+}
+
+/// Parses `code` into a `Journal`.
+pub fn parse_journal(code: &str, settings: &Settings) -> Result<Journal> {
+    let tree = parse(code)?;
+    let reader = Reader {
+        code: code.as_bytes(),
+        decimal_mark: decimal_mark(code, settings.dialect),
+    };
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let mut items = vec![];
+    for child in root
+        .children(&mut cursor)
+        .filter(|c| c.kind() == "journal_item")
+    {
+        let node = child.child(0).err_at_loc(&child)?;
+        let item = match node.kind() {
+            "comment" | "block_comment" | "block_test" => Item::Comment(Comment {
+                line: Some(line(node)),
+                text: reader.text(node)?,
+            }),
+            "directive" => Item::Directive(read_directive(&reader, node)?),
+            "xact" => Item::Transaction(read_transaction(&reader, node)?),
+            _ => continue,
+        };
+        items.push(item);
+    }
+    Ok(Journal { items })
+}
+
+fn line(node: Node) -> usize {
+    node.start_position().row + 1
+}
+
+fn read_directive(reader: &Reader, node: Node) -> Result<Directive> {
+    let child = node.child(0).err_at_loc(&node)?;
+    let mut directive = Directive {
+        line: Some(line(node)),
+        ..Default::default()
+    };
+    match child.kind() {
+        "account_directive" | "commodity_directive" => {
+            directive.kind = child.kind().trim_end_matches("_directive").to_string();
+            directive.value = Some(reader.text(child.named_child(0).err_at_loc(&child)?)?);
+            let mut cursor = child.walk();
+            for subdirective in child
+                .children(&mut cursor)
+                .filter(|c| c.kind().ends_with("_subdirective"))
+            {
+                let subdirective = subdirective.child(0).err_at_loc(&subdirective)?;
+                directive
+                    .subdirectives
+                    .push(read_subdirective(reader, subdirective)?);
+            }
+        }
+        "tag_directive" => {
+            directive.kind = "tag".to_string();
+            directive.value = reader.child_text(child, "tag")?;
+            let mut cursor = child.walk();
+            for subdirective in child
+                .named_children(&mut cursor)
+                .filter(|c| c.kind().ends_with("_subdirective"))
+            {
+                directive
+                    .subdirectives
+                    .push(read_subdirective(reader, subdirective)?);
+            }
+        }
+        _ => {
+            // `option`, `word_directive` and `char_directive` are a keyword and its arguments.
+            let text = reader.text(child)?;
+            let (kind, value) = match text.split_once(char::is_whitespace) {
+                Some((kind, value)) => (kind.to_string(), Some(value.trim().to_string())),
+                None => (text, None),
+            };
+            directive.kind = kind;
+            directive.value = value;
+        }
+    }
+    Ok(directive)
+}
+
+fn read_subdirective(reader: &Reader, node: Node) -> Result<Subdirective> {
+    let kind = node.kind().trim_end_matches("_subdirective").to_string();
+    let value = match node.kind() {
+        "format_subdirective" => reader.child_text(node, "amount")?,
+        _ => reader.child_text(node, "value")?,
+    };
+    Ok(Subdirective { kind, value })
+}
+
+fn read_transaction(reader: &Reader, node: Node) -> Result<Transaction> {
+    let child = node.child(0).err_at_loc(&node)?;
+    let mut transaction = Transaction {
+        kind: match child.kind() {
+            "periodic_xact" => TransactionKind::Periodic,
+            "automated_xact" => TransactionKind::Automated,
+            _ => TransactionKind::Plain,
+        },
+        line: Some(line(node)),
+        ..Default::default()
+    };
+    let mut cursor = child.walk();
+    for child in child.named_children(&mut cursor) {
+        match child.kind() {
+            "date" => transaction.date = Some(reader.text(child)?),
+            "effective_date" => transaction.effective_date = Some(reader.text(child)?),
+            "status" => transaction.status = Some(reader.text(child)?),
+            "code" => transaction.code = Some(reader.text(child)?),
+            "payee" => transaction.payee = Some(reader.text(child)?),
+            "interval" => transaction.interval = Some(reader.text(child)?),
+            "query" => transaction.query = Some(reader.text(child)?),
+            "posting" => transaction.postings.push(read_posting(reader, child)?),
+            "note" => match transaction.postings.last_mut() {
+                Some(posting) => posting.notes.push(reader.text(child)?),
+                None => transaction.notes.push(reader.text(child)?),
+            },
+            _ => {}
+        }
+    }
+    Ok(transaction)
+}
+
+fn read_posting(reader: &Reader, node: Node) -> Result<Posting> {
+    let mut posting = Posting {
+        line: Some(line(node)),
+        ..Default::default()
+    };
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        match child.kind() {
+            "status" => posting.status = Some(reader.text(child)?),
+            "account" => posting.account = reader.text(child)?,
+            "amount" => {
+                posting.amount = Some(read_amount(reader, child)?);
+                posting.cost = read_cost(reader, node, child);
+            }
+            "price" => {
+                let operator = reader.text(child.child(0).err_at_loc(&child)?)?;
+                posting.price = Some(Price {
+                    kind: if operator == "@@" {
+                        PriceKind::Total
+                    } else {
+                        PriceKind::Unit
+                    },
+                    amount: read_amount(reader, find_amount(child)?)?,
+                });
+            }
+            "balance_assertion" => {
+                let amount = find_amount(child)?;
+                let operator =
+                    std::str::from_utf8(&reader.code[child.start_byte()..amount.start_byte()])?
+                        .trim();
+                posting.balance_assertion = Some(BalanceAssertion {
+                    operator: if operator.is_empty() {
+                        default_assertion_operator()
+                    } else {
+                        operator.to_string()
+                    },
+                    amount: read_amount(reader, amount)?,
+                });
+            }
+            "note" => posting.notes.push(reader.text(child)?),
+            _ => {}
+        }
+    }
+    Ok(posting)
+}
+
+/// Reads the lot price written between `amount` and whatever follows it in `posting`, from the
+/// source.
+fn read_cost(reader: &Reader, posting: Node, amount: Node) -> Option<Price> {
+    let text = std::str::from_utf8(&reader.code[lot_annotation(posting, amount)]).ok()?;
+    let text = text.lines().next()?.trim();
+    let (kind, inner) = match text.strip_prefix("{{") {
+        Some(rest) => (PriceKind::Total, rest.split_once("}}")?.0),
+        None => (PriceKind::Unit, text.strip_prefix('{')?.split_once('}')?.0),
+    };
+    Some(Price {
+        kind,
+        amount: parse_amount(inner, reader.decimal_mark),
+    })
+}
+
+/// Reads an amount like `10 EUR`, `$10` or `-5`, from text the grammar does not parse.
+pub(crate) fn parse_amount(text: &str, decimal_mark: Option<char>) -> Amount {
+    let text = text.trim();
+    let is_quantity = |c: char| c.is_ascii_digit() || "-+.,' _".contains(c);
+    // A sign written before a commodity, like `-$12.50`, belongs to the quantity.
+    if let Some(rest) = text.strip_prefix(['-', '+']) {
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with(is_quantity) {
+            let mut amount = parse_amount(rest, decimal_mark);
+            if text.starts_with('-') {
+                amount.quantity = format!("-{}", amount.quantity);
+                amount.value = normalize_quantity(&amount.quantity, decimal_mark);
+            }
+            return amount;
+        }
+    }
+    let (quantity, commodity, side) = if text.starts_with(is_quantity) {
+        let end = text.find(|c| !is_quantity(c)).unwrap_or(text.len());
+        (&text[..end], &text[end..], Side::Right)
+    } else {
+        let start = text.find(is_quantity).unwrap_or(text.len());
+        (&text[start..], &text[..start], Side::Left)
+    };
+    let commodity = commodity.trim();
+    Amount {
+        quantity: quantity.trim().to_string(),
+        value: normalize_quantity(quantity, decimal_mark),
+        commodity: (!commodity.is_empty()).then(|| commodity.to_string()),
+        side: (!commodity.is_empty()).then_some(side),
+    }
+}
+
+fn find_amount(node: Node) -> Result<Node> {
+    let mut cursor = node.walk();
+    let amount = node
+        .named_children(&mut cursor)
+        .find(|c| c.kind() == "amount");
+    amount.err_at_loc(&node)
+}
+
+fn read_amount(reader: &Reader, node: Node) -> Result<Amount> {
+    let mut cursor = node.walk();
+    let quantity = node
+        .named_children(&mut cursor)
+        .find(|c| c.kind() == "quantity" || c.kind() == "negative_quantity");
+    let commodity = node
+        .named_children(&mut cursor)
+        .find(|c| c.kind() == "commodity");
+    let quantity_text = match quantity {
+        Some(quantity) => reader.text(quantity)?,
+        None => String::new(),
+    };
+    let side = match (quantity, commodity) {
+        (Some(quantity), Some(commodity)) if commodity.start_byte() < quantity.start_byte() => {
+            Some(Side::Left)
+        }
+        (_, Some(_)) => Some(Side::Right),
+        _ => None,
+    };
+    Ok(Amount {
+        value: normalize_quantity(&quantity_text, reader.decimal_mark),
+        quantity: quantity_text,
+        commodity: commodity.map(|c| reader.text(c)).transpose()?,
+        side,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STOCKS: &str = "2024/01/02 Broker
+    Assets:Stocks  10 AAPL {50.00 USD}
+    Assets:Funds  2 VWCE {{200 EUR}} @ 101 EUR
+    Assets:Cash
+";
+
+    fn amount(quantity: &str, commodity: &str) -> Amount {
+        Amount {
+            quantity: quantity.to_string(),
+            value: normalize_quantity(quantity, None),
+            commodity: Some(commodity.to_string()),
+            side: Some(Side::Right),
+        }
+    }
+
+    fn purchase() -> Journal {
+        Journal {
+            items: vec![Item::Transaction(Transaction {
+                date: Some("2024/01/02".to_string()),
+                payee: Some("Broker".to_string()),
+                postings: vec![
+                    Posting {
+                        account: "Assets:Stocks".to_string(),
+                        amount: Some(amount("10", "AAPL")),
+                        cost: Some(Price {
+                            kind: PriceKind::Unit,
+                            amount: amount("50.00", "USD"),
+                        }),
+                        ..Default::default()
+                    },
+                    Posting {
+                        account: "Assets:Cash".to_string(),
+                        amount: Some(amount("-500.00", "USD")),
+                        notes: vec!["; paid".to_string()],
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            })],
+        }
+    }
+
+    /// Drops the line numbers, which change when the journal is formatted.
+    fn without_lines(mut journal: Journal) -> Journal {
+        for item in &mut journal.items {
+            if let Item::Transaction(transaction) = item {
+                transaction.line = None;
+                for posting in &mut transaction.postings {
+                    posting.line = None;
+                }
+            }
+        }
+        journal
+    }
+
+    #[test]
+    fn writes_ledger_text() {
+        assert_eq!(
+            purchase().to_string(),
+            "2024/01/02 Broker
+    Assets:Stocks  10 AAPL {50.00 USD}
+    Assets:Cash  -500.00 USD  ; paid
+
+"
+        );
+    }
+
+    #[test]
+    fn writes_one_csv_row_per_posting() {
+        let csv = purchase().to_csv(b',').unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER.join(",").as_str()));
+        assert_eq!(
+            lines.next(),
+            Some("2024/01/02,,,,Broker,,Assets:Stocks,10,AAPL,,,")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("2024/01/02,,,,Broker,,Assets:Cash,-500.00,USD,,,paid")
+        );
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn writes_tsv_rows() {
+        let tsv = purchase().to_csv(b'\t').unwrap();
+        let mut lines = tsv.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER.join("\t").as_str()));
+        assert_eq!(
+            lines.next(),
+            Some("2024/01/02\t\t\t\tBroker\t\tAssets:Stocks\t10\tAAPL\t\t\t")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("2024/01/02\t\t\t\tBroker\t\tAssets:Cash\t-500.00\tUSD\t\t\tpaid")
+        );
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn quotes_fields_holding_the_delimiter() {
+        let mut journal = purchase();
+        let Item::Transaction(transaction) = &mut journal.items[0] else {
+            unreachable!()
+        };
+        transaction.payee = Some("Smith; \"Bob\"".to_string());
+        transaction.postings[1].notes = vec!["; paid, in cash".to_string()];
+        transaction.postings[1].price = Some(Price {
+            kind: PriceKind::Unit,
+            amount: amount("0.9", "EUR"),
+        });
+
+        let csv = journal.to_csv(b';').unwrap();
+        assert_eq!(
+            csv.lines().nth(2),
+            Some(
+                "2024/01/02;;;;\"Smith; \"\"Bob\"\"\";;Assets:Cash;-500.00;USD;@ 0.9 EUR;;\
+                 paid, in cash"
+            )
+        );
+        let csv = journal.to_csv(b',').unwrap();
+        assert_eq!(
+            csv.lines().nth(2),
+            Some(
+                "2024/01/02,,,,\"Smith; \"\"Bob\"\"\",,Assets:Cash,-500.00,USD,@ 0.9 EUR,,\
+                 \"paid, in cash\""
+            )
+        );
+    }
+
+    #[test]
+    fn reads_lot_costs() {
+        let journal = parse_journal(STOCKS, &Settings::default()).unwrap();
+        let Item::Transaction(transaction) = &journal.items[0] else {
+            panic!("expected a transaction");
+        };
+        let cost = |index: usize| transaction.postings[index].cost.clone();
+        assert_eq!(
+            cost(0),
+            Some(Price {
+                kind: PriceKind::Unit,
+                amount: amount("50.00", "USD"),
+            })
+        );
+        assert_eq!(
+            cost(1),
+            Some(Price {
+                kind: PriceKind::Total,
+                amount: amount("200", "EUR"),
+            })
+        );
+        assert_eq!(cost(2), None);
+    }
+
+    #[test]
+    fn keeps_lot_costs_through_json() {
+        let settings = Settings::default();
+        let journal = parse_journal(STOCKS, &settings).unwrap();
+        let json = serde_json::to_string(&journal).unwrap();
+        let formatted = beautify_journal(&serde_json::from_str(&json).unwrap(), &settings).unwrap();
+        assert!(formatted.contains("{50.00 USD}"), "{formatted}");
+        assert!(formatted.contains("{{200 EUR}} @ 101 EUR"), "{formatted}");
+        let reread = parse_journal(&formatted, &settings).unwrap();
+        assert_eq!(without_lines(reread), without_lines(journal));
+    }
+
+    #[test]
+    fn keeps_balance_assignments_apart_from_their_account() {
+        let json = r#"{"items": [{"type": "transaction", "date": "2024/01/02", "payee": "Count",
+            "postings": [
+                {"account": "Assets:Cash",
+                 "balance_assertion": {"amount": {"quantity": "100", "commodity": "EUR"}}},
+                {"account": "Equity:Adjustments"}
+            ]}]}"#;
+        let journal: Journal = serde_json::from_str(json).unwrap();
+        let Item::Transaction(transaction) = &journal.items[0] else {
+            panic!("expected a transaction");
+        };
+        assert_eq!(
+            transaction.postings[0].to_string(),
+            "    Assets:Cash  = 100 EUR\n"
+        );
+        // What `--from-json` does.
+        let settings = Settings::default();
+        let formatted = beautify_journal(&journal, &settings).unwrap();
+        let reread = parse_journal(&formatted, &settings).unwrap();
+        let Item::Transaction(reread) = &reread.items[0] else {
+            panic!("expected a transaction");
+        };
+        assert_eq!(reread.postings[0].account, "Assets:Cash");
+        let assertion = reread.postings[0].balance_assertion.as_ref().unwrap();
+        assert_eq!(assertion.operator, "=");
+        assert_eq!(assertion.amount.to_string(), "100 EUR");
+    }
+}
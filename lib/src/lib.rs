@@ -5,7 +5,29 @@
  */
 
 mod args;
+mod beancount;
 mod beautifier;
+mod classifier;
+mod config;
+mod diagnostics;
+mod document;
+mod duplicates;
+mod edits;
+mod import;
+mod journal;
+mod number;
+mod ofx;
 
 pub use beautifier::*;
+pub use classifier::*;
 pub use args::*;
+pub use beancount::*;
+pub use config::*;
+pub use diagnostics::*;
+pub use document::*;
+pub use duplicates::*;
+pub use edits::*;
+pub use import::*;
+pub use journal::*;
+pub use number::*;
+pub use ofx::*;
@@ -0,0 +1,118 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use super::args::Dialect;
+
+/// Returns the decimal mark quantities in `code` are written with, when the journal sets one with
+/// hledger's `decimal-mark` directive. Otherwise it is guessed for each quantity.
+pub fn decimal_mark(code: &str, dialect: Dialect) -> Option<char> {
+    match dialect {
+        Dialect::Ledger => None,
+        Dialect::Hledger => code.lines().find_map(|line| {
+            line.trim_start()
+                .strip_prefix("decimal-mark")?
+                .trim()
+                .chars()
+                .next()
+                .filter(|c| *c == '.' || *c == ',')
+        }),
+    }
+}
+
+/// Converts a quantity as written in a journal, like `-1.234,56` or `1 234.56`, into a plain
+/// number like `-1234.56`. Without a known `decimal_mark`, it is guessed like hledger does: the
+/// last of `.` and `,` is the decimal mark, unless it appears more than once.
+pub fn normalize_quantity(text: &str, decimal_mark: Option<char>) -> Option<String> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let decimal_mark = decimal_mark.or_else(|| guess_decimal_mark(digits));
+    let mut number = String::with_capacity(digits.len() + 1);
+    if negative {
+        number.push('-');
+    }
+    let mut has_digits = false;
+    for c in digits.chars() {
+        match c {
+            '0'..='9' => {
+                has_digits = true;
+                number.push(c);
+            }
+            c if Some(c) == decimal_mark => number.push('.'),
+            '.' | ',' | ' ' | '_' | '\'' => {}
+            _ => return None,
+        }
+    }
+    has_digits.then_some(number)
+}
+
+fn guess_decimal_mark(digits: &str) -> Option<char> {
+    let mark = digits.chars().rev().find(|c| *c == '.' || *c == ',')?;
+    (digits.matches(mark).count() == 1).then_some(mark)
+}
+
+/// Adds two quantities written as plain numbers, like the ones `normalize_quantity` returns,
+/// keeping every decimal of both.
+pub(crate) fn add_quantities(a: &str, b: &str) -> Option<String> {
+    let decimals = |number: &str| {
+        number
+            .split_once('.')
+            .map_or(0, |(_, decimals)| decimals.len())
+    };
+    let scale = decimals(a).max(decimals(b));
+    let scaled = |number: &str| -> Option<i128> {
+        let (integer, decimals) = number.split_once('.').unwrap_or((number, ""));
+        let zeros = "0".repeat(scale - decimals.len());
+        format!("{integer}{decimals}{zeros}").parse().ok()
+    };
+    let sum = scaled(a)?.checked_add(scaled(b)?)?;
+    let digits = format!("{:0>width$}", sum.unsigned_abs(), width = scale + 1);
+    let (integer, decimals) = digits.split_at(digits.len() - scale);
+    let mut number = if sum < 0 { "-" } else { "" }.to_string() + integer;
+    if scale > 0 {
+        number.push('.');
+        number += decimals;
+    }
+    Some(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_decimal_mark_directive_of_hledger() {
+        let code = "decimal-mark ,\n\n2024-01-01 Shop\n";
+        assert_eq!(decimal_mark(code, Dialect::Hledger), Some(','));
+        assert_eq!(decimal_mark("2024-01-01 Shop\n", Dialect::Hledger), None);
+        assert_eq!(decimal_mark(code, Dialect::Ledger), None);
+    }
+
+    #[test]
+    fn guesses_the_decimal_mark() {
+        let quantity = |text| normalize_quantity(text, None);
+        assert_eq!(quantity("1.234,56").as_deref(), Some("1234.56"));
+        assert_eq!(quantity("1,234.56").as_deref(), Some("1234.56"));
+        assert_eq!(quantity("1,234,567").as_deref(), Some("1234567"));
+        assert_eq!(quantity("-1 234.5").as_deref(), Some("-1234.5"));
+        assert_eq!(quantity("+10").as_deref(), Some("10"));
+        assert_eq!(quantity("abc"), None);
+        assert_eq!(quantity("-"), None);
+    }
+
+    #[test]
+    fn uses_a_known_decimal_mark() {
+        assert_eq!(
+            normalize_quantity("1.234,56", Some(',')).as_deref(),
+            Some("1234.56")
+        );
+        assert_eq!(
+            normalize_quantity("1.234", Some(',')).as_deref(),
+            Some("1234")
+        );
+    }
+}
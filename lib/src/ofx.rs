@@ -0,0 +1,282 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of
+ * the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+
+use super::import::{negate, Entry, ImportRules};
+use super::journal::{parse_amount, Item, Journal};
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+
+/// Whether `text` is an OFX or QFX statement rather than a CSV one.
+pub fn is_ofx(text: &str) -> bool {
+    text.to_ascii_uppercase().contains("<OFX>")
+}
+
+/// Reads the `STMTTRN` records of the OFX or QFX statement `ofx`, either SGML (version 1) or XML
+/// (version 2), into a journal with one balanced transaction each. The `FITID` of each record is
+/// kept as a tag of its transaction.
+pub fn import_ofx(ofx: &str, rules: &ImportRules) -> Result<Journal> {
+    let mut items = vec![];
+    let mut account_id = None;
+    let mut currency = None;
+    // The fields of the `STMTTRN` being read, if any.
+    let mut record: Option<HashMap<String, String>> = None;
+    for (tag, value) in tokens(ofx) {
+        match (tag.as_str(), &mut record) {
+            ("STMTTRN", None) => record = Some(HashMap::new()),
+            ("/STMTTRN", Some(_)) => {
+                let fields = record.take().unwrap_or_default();
+                let id = fields.get("FITID").cloned().unwrap_or_default();
+                let entry = entry(rules, &fields, account_id.as_deref(), currency.clone())
+                    .with_context(|| format!("Could not import the transaction `{id}`"))?;
+                items.push(Item::Transaction(rules.transaction(entry)));
+            }
+            (_, Some(fields)) if !value.is_empty() => {
+                fields.entry(tag).or_insert(value);
+            }
+            ("ACCTID", None) => account_id = Some(value),
+            ("CURDEF", None) => currency = Some(value),
+            _ => {}
+        }
+    }
+    Ok(Journal { items })
+}
+
+fn entry(
+    rules: &ImportRules,
+    fields: &HashMap<String, String>,
+    account_id: Option<&str>,
+    currency: Option<String>,
+) -> Result<Entry> {
+    let field = |name: &str| fields.get(name).cloned();
+    let account = account_id
+        .and_then(|id| rules.accounts.get(id))
+        .or(rules.account.as_ref())
+        .ok_or_else(|| {
+            anyhow!(
+                "no account is set for the statement of `{}`, add it to [accounts]",
+                account_id.unwrap_or_default()
+            )
+        })?;
+    let posted = field("DTPOSTED").ok_or_else(|| anyhow!("DTPOSTED is missing"))?;
+    let date = NaiveDate::parse_from_str(posted.get(..8).unwrap_or_default(), "%Y%m%d")
+        .with_context(|| format!("`{posted}` is not a date"))?;
+    let quantity = field("TRNAMT").ok_or_else(|| anyhow!("TRNAMT is missing"))?;
+    let mut amount = parse_amount(&quantity, rules.decimal_mark);
+    if amount.value.is_none() {
+        return Err(anyhow!("`{quantity}` is not an amount"));
+    }
+    if rules.invert_sign {
+        amount = negate(amount);
+    }
+    let (payee, note) = match (field("NAME"), field("MEMO")) {
+        (Some(name), memo) => (Some(name), memo),
+        (None, memo) => (memo, None),
+    };
+    Ok(Entry {
+        account: account.clone(),
+        date,
+        payee,
+        code: field("CHECKNUM"),
+        note,
+        amount: rules.amount(amount, field("CURSYM").or(currency)),
+        tags: field("FITID").map(|id| ("FITID", id)).into_iter().collect(),
+    })
+}
+
+/// Splits `ofx` into its tags, like `STMTTRN` or `/STMTTRN`, each with the text that follows it.
+/// This reads SGML, where elements that hold a value are not closed, and XML alike.
+fn tokens(ofx: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    ofx.split('<').skip(1).filter_map(|token| {
+        let (tag, value) = token.split_once('>')?;
+        if tag.starts_with('?') || tag.starts_with('!') {
+            return None;
+        }
+        let tag = tag.split_whitespace().next()?.to_ascii_uppercase();
+        Some((tag, decode_entities(value.trim())))
+    })
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SGML: &str = "OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+
+<OFX>
+<BANKMSGSRSV1><STMTTRNRS><STMTRS>
+<CURDEF>EUR
+<BANKACCTFROM>
+<BANKID>123
+<ACCTID>000123456
+</BANKACCTFROM>
+<BANKTRANLIST>
+<STMTTRN>
+<TRNTYPE>DEBIT
+<DTPOSTED>20240105120000.000[-5:EST]
+<TRNAMT>-12.50
+<FITID>2024010501
+<NAME>Bakery &amp; Co
+<MEMO>Bread
+</STMTTRN>
+<STMTTRN>
+<TRNTYPE>CREDIT
+<DTPOSTED>20240106[+1:CET]
+<TRNAMT>1000.00
+<FITID>2024010602
+<NAME>Salary
+<CHECKNUM>42
+</STMTTRN>
+</BANKTRANLIST>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>
+";
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<?OFX OFXHEADER="200" VERSION="211" SECURITY="NONE"?>
+<OFX>
+  <CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS>
+    <CURDEF>USD</CURDEF>
+    <CCACCTFROM><ACCTID>4111XXXXXXXX1111</ACCTID></CCACCTFROM>
+    <BANKTRANLIST>
+      <STMTTRN>
+        <TRNTYPE>DEBIT</TRNTYPE>
+        <DTPOSTED>20240301083000[-8:PST]</DTPOSTED>
+        <TRNAMT>-45.99</TRNAMT>
+        <FITID>CC-0001</FITID>
+        <NAME>Book store</NAME>
+      </STMTTRN>
+      <STMTTRN>
+        <TRNTYPE>CREDIT</TRNTYPE>
+        <DTPOSTED>20240302</DTPOSTED>
+        <TRNAMT>10.00</TRNAMT>
+        <FITID>CC-0002</FITID>
+        <MEMO>Refund</MEMO>
+      </STMTTRN>
+    </BANKTRANLIST>
+  </CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1>
+</OFX>
+"#;
+
+    fn rules(toml: &str) -> ImportRules {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn transactions(journal: &Journal) -> Vec<&crate::Transaction> {
+        journal
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::Transaction(transaction) => transaction,
+                _ => panic!("expected a transaction"),
+            })
+            .collect()
+    }
+
+    fn amount(transaction: &crate::Transaction, posting: usize) -> String {
+        transaction.postings[posting]
+            .amount
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn recognizes_statements() {
+        assert!(is_ofx(SGML));
+        assert!(is_ofx(XML));
+        assert!(!is_ofx("Date,Amount\n2024-01-01,10\n"));
+    }
+
+    #[test]
+    fn reads_sgml_statements() {
+        let rules = rules(
+            r#"
+            default-account = "Expenses:Unknown"
+            [accounts]
+            "000123456" = "Assets:Checking"
+            "#,
+        );
+        let journal = import_ofx(SGML, &rules).unwrap();
+        let transactions = transactions(&journal);
+        assert_eq!(transactions.len(), 2);
+
+        let bakery = transactions[0];
+        assert_eq!(bakery.date.as_deref(), Some("2024-01-05"));
+        assert_eq!(bakery.payee.as_deref(), Some("Bakery & Co"));
+        assert_eq!(bakery.notes, ["; Bread", "; FITID: 2024010501"]);
+        assert_eq!(bakery.postings[0].account, "Assets:Checking");
+        assert_eq!(bakery.postings[1].account, "Expenses:Unknown");
+        assert_eq!(amount(bakery, 0), "-12.50 EUR");
+        assert_eq!(amount(bakery, 1), "12.50 EUR");
+
+        let salary = transactions[1];
+        assert_eq!(salary.date.as_deref(), Some("2024-01-06"));
+        assert_eq!(salary.code.as_deref(), Some("(42)"));
+        assert_eq!(salary.notes, ["; FITID: 2024010602"]);
+        assert_eq!(amount(salary, 0), "1000.00 EUR");
+    }
+
+    #[test]
+    fn reads_xml_statements() {
+        let rules = rules(
+            r#"
+            default-account = "Expenses:Unknown"
+            [accounts]
+            "4111XXXXXXXX1111" = "Liabilities:Visa"
+            "#,
+        );
+        let journal = import_ofx(XML, &rules).unwrap();
+        let transactions = transactions(&journal);
+        assert_eq!(transactions.len(), 2);
+
+        let books = transactions[0];
+        assert_eq!(books.date.as_deref(), Some("2024-03-01"));
+        assert_eq!(books.payee.as_deref(), Some("Book store"));
+        assert_eq!(books.notes, ["; FITID: CC-0001"]);
+        assert_eq!(books.postings[0].account, "Liabilities:Visa");
+        assert_eq!(amount(books, 0), "-45.99 USD");
+
+        // Without NAME, the memo is the payee.
+        let refund = transactions[1];
+        assert_eq!(refund.date.as_deref(), Some("2024-03-02"));
+        assert_eq!(refund.payee.as_deref(), Some("Refund"));
+        assert_eq!(amount(refund, 0), "10.00 USD");
+    }
+
+    #[test]
+    fn inverts_signs() {
+        let rules = rules(
+            r#"
+            account = "Liabilities:Visa"
+            default-account = "Expenses:Unknown"
+            invert-sign = true
+            "#,
+        );
+        let journal = import_ofx(XML, &rules).unwrap();
+        let transactions = transactions(&journal);
+        assert_eq!(amount(transactions[0], 0), "45.99 USD");
+        assert_eq!(amount(transactions[1], 0), "-10.00 USD");
+    }
+
+    #[test]
+    fn requires_an_account() {
+        let rules = rules(r#"default-account = "Expenses:Unknown""#);
+        let err = import_ofx(SGML, &rules).unwrap_err();
+        assert!(format!("{err:#}").contains("000123456"));
+    }
+}